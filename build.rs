@@ -0,0 +1,9 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Fall back to the vendored protoc so a clean checkout builds without a
+    // system install; an explicit PROTOC still takes precedence.
+    if std::env::var_os("PROTOC").is_none() {
+        std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    }
+    tonic_build::compile_protos("proto/three_grams.proto")?;
+    Ok(())
+}
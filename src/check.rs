@@ -0,0 +1,254 @@
+use crate::normalize::{self, Pipeline};
+use crate::query_3_grams::{self, specs};
+use crate::tokenizer;
+use itertools::Itertools;
+use scylla::Session;
+use std::error::Error;
+
+static DEFAULT_RATIO: f64 = 10.0;
+static DEFAULT_SUGGESTIONS: usize = 5;
+
+pub struct CheckOptions {
+    pub text: String,
+    pub ratio: f64,
+    pub suggestions: usize,
+}
+
+/// Replacements for the word at `position` (1 to 3) of a flagged 3-gram, with
+/// the frequency of the 3-gram they would make.
+pub struct Suggestion {
+    pub position: usize,
+    pub word: String,
+    pub replacements: Vec<(String, i32)>,
+}
+
+/// A 3-gram of the text that is much rarer than its alternatives.
+pub struct Suspicion {
+    pub input: specs::ThreeGramInput,
+    pub freq: i32,
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl CheckOptions {
+    /// Parses `<text...> [--ratio <r>] [--suggestions <n>]`. A 3-gram is
+    /// flagged when a replacement of one of its words is at least `ratio`
+    /// times as frequent.
+    pub fn from(args: &[String]) -> Result<CheckOptions, String> {
+        let mut words = Vec::new();
+        let mut ratio = DEFAULT_RATIO;
+        let mut suggestions = DEFAULT_SUGGESTIONS;
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--ratio" => {
+                    ratio = iter
+                        .next()
+                        .and_then(|value| value.parse::<f64>().ok())
+                        .filter(|value| *value >= 1.0)
+                        .ok_or("--ratio expects a number of at least 1")?
+                }
+                "--suggestions" => {
+                    suggestions = iter
+                        .next()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .ok_or("--suggestions expects a number")?
+                }
+                _ if !arg.starts_with("--") => words.push(arg.clone()),
+                _ => return Err(format!("Unknown check option: {}", arg)),
+            }
+        }
+
+        if words.is_empty() {
+            return Err(String::from(
+                "Usage: check <text...> [--ratio <r>] [--suggestions <n>]",
+            ));
+        }
+        Ok(CheckOptions {
+            text: words.join(" "),
+            ratio,
+            suggestions,
+        })
+    }
+}
+
+/// The words of `word_pair_map` at least `ratio` times as frequent as `freq`
+/// (as 1 when unseen), most frequent first.
+fn replacements(
+    result: &specs::QueryResult,
+    word: &str,
+    freq: i32,
+    options: &CheckOptions,
+) -> Vec<(String, i32)> {
+    let threshold = freq.max(1) as f64 * options.ratio;
+    result
+        .word_pair_map
+        .iter()
+        .filter(|(candidate, candidate_freq)| {
+            candidate.as_str() != word && **candidate_freq as f64 >= threshold
+        })
+        .sorted_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)))
+        .take(options.suggestions)
+        .map(|(candidate, candidate_freq)| (candidate.clone(), *candidate_freq))
+        .collect()
+}
+
+/// Checks one 3-gram with the same three pair lookups as `get_3_gram`: the
+/// continuations of `word_1 word_2`, `word_1 _ word_3` and `_ word_2 word_3`.
+async fn check_three_gram(
+    session: &Session,
+    input: specs::ThreeGramInput,
+    options: &CheckOptions,
+) -> Result<Option<Suspicion>, Box<dyn Error>> {
+    let (result_1_2_pk, result_1_3_pk, result_2_3_pk) = futures::try_join!(
+        query_3_grams::get_pair(
+            session,
+            specs::PairTable::FirstSecond,
+            specs::WordPair::new(input.word_1.clone(), input.word_2.clone()),
+        ),
+        query_3_grams::get_pair(
+            session,
+            specs::PairTable::FirstThird,
+            specs::WordPair::new(input.word_1.clone(), input.word_3.clone()),
+        ),
+        query_3_grams::get_pair(
+            session,
+            specs::PairTable::SecondThird,
+            specs::WordPair::new(input.word_2.clone(), input.word_3.clone()),
+        ),
+    )?;
+    let freq = result_1_2_pk
+        .word_pair_map
+        .get(&input.word_3)
+        .copied()
+        .unwrap_or(0);
+
+    let suggestions: Vec<Suggestion> = [
+        (1, &input.word_1, &result_2_3_pk),
+        (2, &input.word_2, &result_1_3_pk),
+        (3, &input.word_3, &result_1_2_pk),
+    ]
+    .into_iter()
+    .map(|(position, word, result)| Suggestion {
+        position,
+        word: word.clone(),
+        replacements: replacements(result, word, freq, options),
+    })
+    .filter(|suggestion| !suggestion.replacements.is_empty())
+    .collect();
+
+    if suggestions.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Suspicion {
+        input,
+        freq,
+        suggestions,
+    }))
+}
+
+/// The 3-grams of consecutive normalized words of `text`. Words the pipeline
+/// removes entirely, like standalone punctuation, are dropped first so they
+/// neither break a 3-gram nor abort the check.
+fn windows(text: &str, pipeline: &Pipeline) -> Result<Vec<specs::ThreeGramInput>, String> {
+    let words: Vec<String> = tokenizer::active()
+        .tokenize(text)
+        .iter()
+        .map(|word| pipeline.normalize(word))
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.len() < 3 {
+        return Err(format!(
+            "Expected at least 3 words after normalization, found {}",
+            words.len()
+        ));
+    }
+    Ok(specs::ThreeGramInput::from_words(&words))
+}
+
+/// Slides over the normalized words of the text and returns the suspicious
+/// 3-grams in text order.
+pub async fn check(
+    session: &Session,
+    options: &CheckOptions,
+) -> Result<Vec<Suspicion>, Box<dyn Error>> {
+    let mut suspicions = Vec::new();
+    for input in windows(&options.text, normalize::active())? {
+        if let Some(suspicion) = check_three_gram(session, input, options).await? {
+            suspicions.push(suspicion);
+        }
+    }
+    Ok(suspicions)
+}
+
+pub fn print(suspicions: &[Suspicion]) {
+    if suspicions.is_empty() {
+        println!("Nothing suspicious found");
+    }
+    for suspicion in suspicions {
+        println!(
+            "\"{} {} {}\": {}",
+            suspicion.input.word_1, suspicion.input.word_2, suspicion.input.word_3, suspicion.freq
+        );
+        for suggestion in &suspicion.suggestions {
+            let replacements = suggestion
+                .replacements
+                .iter()
+                .map(|(word, freq)| format!("{} ({})", word, freq))
+                .join(", ");
+            println!(
+                " word {} \"{}\": {}",
+                suggestion.position, suggestion.word, replacements
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(inputs: &[specs::ThreeGramInput]) -> Vec<[&str; 3]> {
+        inputs
+            .iter()
+            .map(|input| [&input.word_1, &input.word_2, &input.word_3].map(String::as_str))
+            .collect()
+    }
+
+    #[test]
+    fn windows_drop_words_normalized_away() {
+        let pipeline = Pipeline::parse("casefold,strip-punctuation").unwrap();
+        let inputs = windows("The cat , sat on - the mat !", &pipeline).unwrap();
+        assert_eq!(
+            words(&inputs),
+            vec![
+                ["the", "cat", "sat"],
+                ["cat", "sat", "on"],
+                ["sat", "on", "the"],
+                ["on", "the", "mat"],
+            ]
+        );
+    }
+
+    #[test]
+    fn windows_need_three_words_after_normalization() {
+        let pipeline = Pipeline::parse("strip-punctuation").unwrap();
+        assert!(windows("hello , world", &pipeline).is_err());
+        assert_eq!(
+            windows("hello , world", &Pipeline::parse("none").unwrap())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn options_reject_unknown_flags() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let options = CheckOptions::from(&args(&["a", "b", "c", "--ratio", "2"])).unwrap();
+        assert_eq!(options.text, "a b c");
+        assert_eq!(options.ratio, 2.0);
+        assert!(CheckOptions::from(&args(&["a", "b", "c", "--ratoi", "2"])).is_err());
+        assert!(CheckOptions::from(&args(&["--ratio", "0.5", "a", "b", "c"])).is_err());
+    }
+}
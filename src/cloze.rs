@@ -0,0 +1,271 @@
+use crate::normalize;
+use crate::query_3_grams::{self, specs};
+use crate::tokenizer;
+use itertools::Itertools;
+use scylla::Session;
+use std::error::Error;
+
+static GAP: &str = "___";
+static DEFAULT_LIMIT: usize = 10;
+static DEFAULT_ALPHA: f64 = 1.0;
+
+/// How the smoothed probabilities of the windows are combined.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Combine {
+    /// `p_1^w_1 * p_2^w_2 * p_3^w_3`: every window must agree.
+    Product,
+    /// `w_1 * p_1 + w_2 * p_2 + w_3 * p_3`: any window may vote.
+    Sum,
+}
+
+pub struct ClozeOptions {
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    /// Weights of the windows (w-2, w-1, _), (w-1, _, w+1) and (_, w+1, w+2).
+    pub weights: [f64; 3],
+    pub alpha: f64,
+    pub combine: Combine,
+    pub limit: usize,
+}
+
+/// The continuations of one window around the gap.
+pub struct Window {
+    pub label: String,
+    pub weight: f64,
+    pub result: specs::QueryResult,
+    pub total: i64,
+}
+
+pub struct Candidate {
+    pub word: String,
+    pub score: f64,
+    /// Frequency and smoothed probability in each window, in window order.
+    pub evidence: Vec<(i32, f64)>,
+}
+
+impl Combine {
+    pub fn parse(name: &str) -> Result<Combine, String> {
+        match name {
+            "product" => Ok(Combine::Product),
+            "sum" => Ok(Combine::Sum),
+            _ => Err(format!("Unknown combination: {}", name)),
+        }
+    }
+}
+
+impl ClozeOptions {
+    /// Parses `<text with ___...> [--weights <w1,w2,w3>] [--alpha <a>]
+    /// [--combine product|sum] [--limit <n>]`. The gap needs at least one
+    /// word on one side, and the words next to it decide which windows apply.
+    pub fn from(args: &[String]) -> Result<ClozeOptions, String> {
+        let mut words = Vec::new();
+        let mut weights = [1.0; 3];
+        let mut alpha = DEFAULT_ALPHA;
+        let mut combine = Combine::Product;
+        let mut limit = DEFAULT_LIMIT;
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--weights" => {
+                    let values: Vec<f64> = iter
+                        .next()
+                        .ok_or("--weights expects three numbers")?
+                        .split(',')
+                        .map(|value| value.trim().parse::<f64>())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| "--weights expects three numbers")?;
+                    weights = values
+                        .try_into()
+                        .map_err(|_| "--weights expects three numbers")?;
+                    if weights.iter().any(|weight| *weight < 0.0) {
+                        return Err(String::from("--weights must not be negative"));
+                    }
+                }
+                "--alpha" => {
+                    alpha = iter
+                        .next()
+                        .and_then(|value| value.parse::<f64>().ok())
+                        .filter(|value| *value > 0.0)
+                        .ok_or("--alpha expects a positive number")?
+                }
+                "--combine" => {
+                    combine =
+                        Combine::parse(iter.next().ok_or("--combine expects product or sum")?)?
+                }
+                "--limit" => {
+                    limit = iter
+                        .next()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .ok_or("--limit expects a number")?
+                }
+                _ if !arg.starts_with("--") => words.push(arg.clone()),
+                _ => return Err(format!("Unknown cloze option: {}", arg)),
+            }
+        }
+
+        let usage = "Usage: cloze <text with ___> [--weights <w1,w2,w3>] [--alpha <a>] [--combine product|sum] [--limit <n>]";
+        let text = words.join(" ");
+        let (before, after) = text.split_once(GAP).ok_or(usage)?;
+        if after.contains(GAP) {
+            return Err(String::from("The text must contain exactly one ___ gap"));
+        }
+        let pipeline = normalize::active();
+        let tokenize = |text: &str| -> Vec<String> {
+            tokenizer::active()
+                .tokenize(text)
+                .iter()
+                .map(|word| pipeline.normalize(word))
+                .filter(|word| !word.is_empty())
+                .collect()
+        };
+        let before = tokenize(before);
+        let after = tokenize(after);
+        if before.is_empty() && after.is_empty() {
+            return Err(String::from(usage));
+        }
+        Ok(ClozeOptions {
+            before,
+            after,
+            weights,
+            alpha,
+            combine,
+            limit,
+        })
+    }
+
+    /// The windows around the gap that the surrounding words fill, with the
+    /// pair each is keyed by.
+    fn windows(&self) -> Vec<(specs::PairTable, specs::WordPair, String, f64)> {
+        let before: Vec<&String> = self.before.iter().rev().take(2).rev().collect();
+        let after: Vec<&String> = self.after.iter().take(2).collect();
+        let mut windows = Vec::new();
+        if before.len() == 2 {
+            windows.push((
+                specs::PairTable::FirstSecond,
+                specs::WordPair::new(before[0].clone(), before[1].clone()),
+                format!("{} {} ___", before[0], before[1]),
+                self.weights[0],
+            ));
+        }
+        if let (Some(previous), Some(next)) = (before.last(), after.first()) {
+            windows.push((
+                specs::PairTable::FirstThird,
+                specs::WordPair::new((*previous).clone(), (*next).clone()),
+                format!("{} ___ {}", previous, next),
+                self.weights[1],
+            ));
+        }
+        if after.len() == 2 {
+            windows.push((
+                specs::PairTable::SecondThird,
+                specs::WordPair::new(after[0].clone(), after[1].clone()),
+                format!("___ {} {}", after[0], after[1]),
+                self.weights[2],
+            ));
+        }
+        windows
+    }
+}
+
+/// Fetches every window around the gap and ranks the words seen in any of
+/// them. Probabilities are add-alpha smoothed over all candidates, so a word
+/// missing from one window is penalized rather than ruled out.
+pub async fn solve(
+    session: &Session,
+    options: &ClozeOptions,
+) -> Result<(Vec<Window>, Vec<Candidate>), Box<dyn Error>> {
+    let mut windows = Vec::new();
+    for (pair_table, word_pair, label, weight) in options.windows() {
+        if weight == 0.0 {
+            continue;
+        }
+        let result = query_3_grams::get_pair(session, pair_table, word_pair).await?;
+        let total = result.word_pair_map.values().map(|freq| *freq as i64).sum();
+        windows.push(Window {
+            label,
+            weight,
+            result,
+            total,
+        });
+    }
+    if windows.is_empty() {
+        return Err("Every window around the gap has a weight of 0".into());
+    }
+
+    let vocabulary: Vec<&String> = windows
+        .iter()
+        .flat_map(|window| window.result.word_pair_map.keys())
+        .unique()
+        .collect();
+    let size = vocabulary.len() as f64;
+    let weight_sum: f64 = windows.iter().map(|window| window.weight).sum();
+
+    let candidates = vocabulary
+        .into_iter()
+        .map(|word| {
+            let evidence: Vec<(i32, f64)> = windows
+                .iter()
+                .map(|window| {
+                    let freq = window.result.word_pair_map.get(word).copied().unwrap_or(0);
+                    let probability = (freq as f64 + options.alpha)
+                        / (window.total as f64 + options.alpha * size);
+                    (freq, probability)
+                })
+                .collect();
+            let score = match options.combine {
+                Combine::Product => windows
+                    .iter()
+                    .zip(&evidence)
+                    .map(|(window, (_, probability))| window.weight * probability.ln())
+                    .sum::<f64>()
+                    .exp(),
+                Combine::Sum => {
+                    windows
+                        .iter()
+                        .zip(&evidence)
+                        .map(|(window, (_, probability))| window.weight * probability)
+                        .sum::<f64>()
+                        / weight_sum
+                }
+            };
+            Candidate {
+                word: word.clone(),
+                score,
+                evidence,
+            }
+        })
+        .sorted_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.word.cmp(&b.word))
+        })
+        .take(options.limit)
+        .collect();
+    Ok((windows, candidates))
+}
+
+pub fn print(windows: &[Window], candidates: &[Candidate]) {
+    for window in windows {
+        println!(
+            "--- {} (weight {}, {} continuations, total {}) ---",
+            window.label,
+            window.weight,
+            window.result.word_pair_map.len(),
+            window.total
+        );
+    }
+    if candidates.is_empty() {
+        println!("No candidates found");
+    }
+    for candidate in candidates {
+        let evidence = windows
+            .iter()
+            .zip(&candidate.evidence)
+            .map(|(window, (freq, probability))| {
+                format!("{}: {} ({:.4})", window.label, freq, probability)
+            })
+            .join(", ");
+        println!(" {}: {:.6} [{}]", candidate.word, candidate.score, evidence);
+    }
+}
@@ -0,0 +1,371 @@
+use crate::compare::ll_term;
+use crate::marginals;
+use crate::normalize;
+use crate::query_3_grams::{self, specs};
+use futures::future::try_join_all;
+use itertools::Itertools;
+use scylla::Session;
+use std::error::Error;
+
+static DEFAULT_LIMIT: usize = 10;
+
+/// The association measure continuations are ranked by.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Measure {
+    Pmi,
+    Npmi,
+    TScore,
+    LogLikelihood,
+}
+
+pub struct CollocationOptions {
+    pub input: specs::ThreeGramInput,
+    pub measure: Measure,
+    pub limit: usize,
+    pub min_freq: i32,
+}
+
+/// How strongly the words of a pair are attracted to the word filling the
+/// free position, compared to chance.
+pub struct Scores {
+    pub pmi: f64,
+    pub npmi: f64,
+    pub t_score: f64,
+    pub log_likelihood: f64,
+}
+
+/// The observed counts of a pair and a free word, on the scale of 3-grams.
+struct Counts {
+    joint: f64,
+    key: f64,
+    free: f64,
+    total: f64,
+}
+
+pub struct Collocation {
+    pub word: String,
+    pub freq: i32,
+    pub scores: Scores,
+}
+
+impl Measure {
+    pub fn parse(name: &str) -> Result<Measure, String> {
+        match name {
+            "pmi" => Ok(Measure::Pmi),
+            "npmi" => Ok(Measure::Npmi),
+            "t-score" => Ok(Measure::TScore),
+            "log-likelihood" | "ll" => Ok(Measure::LogLikelihood),
+            _ => Err(format!("Unknown association measure: {}", name)),
+        }
+    }
+}
+
+impl Scores {
+    pub fn get(&self, measure: Measure) -> f64 {
+        match measure {
+            Measure::Pmi => self.pmi,
+            Measure::Npmi => self.npmi,
+            Measure::TScore => self.t_score,
+            Measure::LogLikelihood => self.log_likelihood,
+        }
+    }
+}
+
+impl Counts {
+    fn scores(&self) -> Scores {
+        let joint = self.joint;
+        let key = self.key;
+        let total = self.total;
+        // The free count is a per-position average of the unigram marginal,
+        // so a word concentrated at this position can fall below the joint
+        // count.
+        let free = self.free.max(joint);
+        if joint <= 0.0 || total <= 0.0 {
+            return Scores {
+                pmi: 0.0,
+                npmi: 0.0,
+                t_score: 0.0,
+                log_likelihood: 0.0,
+            };
+        }
+
+        let expected = key * free / total;
+        let pmi = (joint / expected).log2();
+        let npmi = if joint >= total {
+            1.0
+        } else {
+            pmi / -(joint / total).log2()
+        };
+        let t_score = (joint - expected) / joint.sqrt();
+
+        let observed = [
+            joint,
+            key - joint,
+            free - joint,
+            (total - key - free + joint).max(0.0),
+        ];
+        let expected = [
+            key * free / total,
+            key * (total - free) / total,
+            (total - key) * free / total,
+            (total - key) * (total - free) / total,
+        ];
+        let log_likelihood = 2.0
+            * observed
+                .iter()
+                .zip(expected.iter())
+                .map(|(observed, expected)| ll_term(*observed, *expected))
+                .sum::<f64>();
+        Scores {
+            pmi,
+            npmi,
+            t_score,
+            log_likelihood,
+        }
+    }
+}
+
+impl CollocationOptions {
+    /// Parses `<word_1> <word_2> <word_3> [--by pmi|npmi|t-score|log-likelihood]
+    /// [--limit <n>] [--min-freq <n>]`. Ranks by log-likelihood by default,
+    /// which, unlike PMI, doesn't favour continuations seen once.
+    pub fn from(args: &[String]) -> Result<CollocationOptions, String> {
+        let mut words = Vec::new();
+        let mut measure = Measure::LogLikelihood;
+        let mut limit = DEFAULT_LIMIT;
+        let mut min_freq = 1;
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--by" => measure = Measure::parse(iter.next().ok_or("--by expects a measure")?)?,
+                "--limit" => {
+                    limit = iter
+                        .next()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .ok_or("--limit expects a number")?
+                }
+                "--min-freq" => {
+                    min_freq = iter
+                        .next()
+                        .and_then(|value| value.parse::<i32>().ok())
+                        .ok_or("--min-freq expects a number")?
+                }
+                _ if !arg.starts_with("--") => words.push(arg.clone()),
+                _ => return Err(format!("Unknown collocations option: {}", arg)),
+            }
+        }
+
+        if words.len() != 3 {
+            return Err(String::from("Usage: collocations <word_1> <word_2> <word_3> [--by pmi|npmi|t-score|log-likelihood] [--limit <n>] [--min-freq <n>]"));
+        }
+        let input =
+            specs::ThreeGramInput::new(words[0].clone(), words[1].clone(), words[2].clone());
+        Ok(CollocationOptions {
+            input: normalize::active().normalize_input(&input)?,
+            measure,
+            limit,
+            min_freq,
+        })
+    }
+}
+
+/// The count of the pair of `result`: the sum of its continuations, which
+/// is exact for every pair table.
+fn key_count(result: &specs::QueryResult) -> f64 {
+    result.word_pair_map.values().map(|freq| *freq as f64).sum()
+}
+
+/// The count of `word` at one position, approximated by its unigram marginal
+/// spread evenly over the three positions.
+async fn free_count(session: &Session, word: &str) -> Result<f64, Box<dyn Error>> {
+    Ok(marginals::unigram(session, word).await? as f64 / 3.0)
+}
+
+/// Scores every continuation of `result` with at least `min_freq` and returns
+/// them ranked by `measure`, best first.
+pub async fn rank(
+    session: &Session,
+    result: &specs::QueryResult,
+    measure: Measure,
+    min_freq: i32,
+) -> Result<Vec<Collocation>, Box<dyn Error>> {
+    let total = marginals::total(session).await? as f64;
+    let key = key_count(result);
+    let candidates: Vec<(&String, &i32)> = result
+        .word_pair_map
+        .iter()
+        .filter(|(_, freq)| **freq >= min_freq)
+        .collect();
+    let free_counts =
+        try_join_all(candidates.iter().map(|(word, _)| free_count(session, word))).await?;
+
+    Ok(candidates
+        .into_iter()
+        .zip(free_counts)
+        .map(|((word, freq), free)| Collocation {
+            word: word.clone(),
+            freq: *freq,
+            scores: Counts {
+                joint: *freq as f64,
+                key,
+                free,
+                total,
+            }
+            .scores(),
+        })
+        .sorted_by(|a, b| {
+            b.scores
+                .get(measure)
+                .total_cmp(&a.scores.get(measure))
+                .then_with(|| a.word.cmp(&b.word))
+        })
+        .collect())
+}
+
+fn print_collocation(word: &str, freq: i32, scores: &Scores) {
+    println!(
+        " {}: freq {}, pmi {:.2}, npmi {:.3}, t-score {:.2}, log-likelihood {:.2}",
+        word, freq, scores.pmi, scores.npmi, scores.t_score, scores.log_likelihood
+    );
+}
+
+/// Prints the association measures of the 3-gram, as its first two words
+/// followed by the third, and the best continuations of each of its pairs.
+pub async fn collocations(
+    session: &Session,
+    options: &CollocationOptions,
+) -> Result<(), Box<dyn Error>> {
+    let input = &options.input;
+    let tables = [
+        (
+            specs::PairTable::FirstSecond,
+            specs::WordPair::new(input.word_1.clone(), input.word_2.clone()),
+            format!("{} {} _____", input.word_1, input.word_2),
+            &input.word_3,
+        ),
+        (
+            specs::PairTable::FirstThird,
+            specs::WordPair::new(input.word_1.clone(), input.word_3.clone()),
+            format!("{} _____ {}", input.word_1, input.word_3),
+            &input.word_2,
+        ),
+        (
+            specs::PairTable::SecondThird,
+            specs::WordPair::new(input.word_2.clone(), input.word_3.clone()),
+            format!("_____ {} {}", input.word_2, input.word_3),
+            &input.word_1,
+        ),
+    ];
+
+    for (index, (pair_table, word_pair, label, word)) in tables.into_iter().enumerate() {
+        let result = query_3_grams::get_pair(session, pair_table, word_pair).await?;
+        if index == 0 {
+            print!("{} {} {}:", input.word_1, input.word_2, input.word_3);
+            match result.word_pair_map.get(word) {
+                Some(freq) => {
+                    let scores = Counts {
+                        joint: *freq as f64,
+                        key: key_count(&result),
+                        free: free_count(session, word).await?,
+                        total: marginals::total(session).await? as f64,
+                    }
+                    .scores();
+                    println!();
+                    print_collocation(word, *freq, &scores);
+                }
+                None => println!(" not found"),
+            }
+        }
+
+        let ranked = rank(session, &result, options.measure, options.min_freq).await?;
+        println!("--- {} ({} continuations) ---", label, ranked.len());
+        for collocation in ranked.iter().take(options.limit) {
+            print_collocation(&collocation.word, collocation.freq, &collocation.scores);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(joint: f64, key: f64, free: f64, total: f64) -> Scores {
+        Counts {
+            joint,
+            key,
+            free,
+            total,
+        }
+        .scores()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn scores_match_known_values() {
+        // Twice as often as chance: 10 observed, 100 * 50 / 1000 = 5 expected.
+        let scores = scores(10.0, 100.0, 50.0, 1000.0);
+        assert_close(scores.pmi, 1.0);
+        assert_close(scores.npmi, 1.0 / 100.0_f64.log2());
+        assert_close(scores.t_score, 5.0 / 10.0_f64.sqrt());
+        let log_likelihood = 2.0
+            * (10.0 * 2.0_f64.ln()
+                + 90.0 * (90.0 / 95.0_f64).ln()
+                + 40.0 * (40.0 / 45.0_f64).ln()
+                + 860.0 * (860.0 / 855.0_f64).ln());
+        assert_close(scores.log_likelihood, log_likelihood);
+        assert_close(scores.get(Measure::LogLikelihood), log_likelihood);
+    }
+
+    #[test]
+    fn chance_cooccurrence_scores_zero() {
+        let scores = scores(5.0, 100.0, 50.0, 1000.0);
+        assert_close(scores.pmi, 0.0);
+        assert_close(scores.npmi, 0.0);
+        assert_close(scores.t_score, 0.0);
+        assert_close(scores.log_likelihood, 0.0);
+    }
+
+    #[test]
+    fn unseen_pairs_and_empty_corpora_score_zero() {
+        for scores in [
+            scores(0.0, 100.0, 50.0, 1000.0),
+            scores(0.0, 0.0, 0.0, 0.0),
+            scores(1.0, 1.0, 1.0, 0.0),
+        ] {
+            for measure in [
+                Measure::Pmi,
+                Measure::Npmi,
+                Measure::TScore,
+                Measure::LogLikelihood,
+            ] {
+                assert_eq!(scores.get(measure), 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn free_count_below_the_joint_count_is_raised_to_it() {
+        let raised = scores(10.0, 100.0, 2.0, 1000.0);
+        let exact = scores(10.0, 100.0, 10.0, 1000.0);
+        assert_close(raised.pmi, exact.pmi);
+        assert_close(raised.log_likelihood, exact.log_likelihood);
+        assert!(raised.log_likelihood.is_finite());
+    }
+
+    #[test]
+    fn a_pair_making_up_the_whole_corpus_has_npmi_one() {
+        let scores = scores(10.0, 10.0, 10.0, 10.0);
+        assert_close(scores.npmi, 1.0);
+        assert_close(scores.pmi, 0.0);
+    }
+}
@@ -0,0 +1,494 @@
+use crate::check;
+use crate::cloze;
+use crate::collocations;
+use crate::compare;
+use crate::corpus::{self, Corpus};
+use crate::export;
+use crate::generate;
+use crate::google_books;
+use crate::grpc;
+use crate::import;
+use crate::leaderboard;
+use crate::marginals;
+use crate::metrics;
+use crate::normalize;
+use crate::query_3_grams;
+use crate::query_n_grams::{self, schema, specs as n_gram_specs};
+use crate::reader;
+use crate::server;
+use crate::stats;
+use crate::summary;
+use crate::trends;
+use scylla::{Session, SessionBuilder};
+use std::error::Error;
+use std::net::SocketAddr;
+
+/// Opens a session without touching any corpus, for commands that create or
+/// list them.
+async fn open_session() -> Result<Session, Box<dyn Error>> {
+    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+
+    let session: Session = SessionBuilder::new().known_node(uri).build().await?;
+    Ok(session)
+}
+
+pub async fn connect() -> Result<Session, Box<dyn Error>> {
+    let session = open_session().await?;
+    normalize::verify(&session).await?;
+    marginals::create(&session, corpus::active()).await?;
+    leaderboard::create(&session, corpus::active()).await?;
+    if trends::enabled() {
+        trends::create(&session).await?;
+    }
+    Ok(session)
+}
+
+/// Returns the value following `flag` in `args`.
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1).cloned())
+}
+
+/// Returns the value following `flag` in `args`, falling back to the
+/// environment variable `env`.
+fn flag_or_env(args: &[String], flag: &str, env: &str) -> Option<String> {
+    flag_value(args, flag).or_else(|| std::env::var(env).ok())
+}
+
+/// Starts the `/metrics` endpoint in the background when `--metrics-addr` or
+/// `METRICS_ADDR` is set. The gRPC server has no HTTP routes of its own, so
+/// this is where it serves its metrics.
+fn spawn_metrics_server(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if let Some(addr) = flag_or_env(args, "--metrics-addr", "METRICS_ADDR") {
+        let addr: SocketAddr = addr
+            .parse()
+            .map_err(|err| format!("Invalid metrics address {}: {}", addr, err))?;
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(addr).await {
+                eprintln!("Metrics endpoint stopped: {}", err);
+            }
+        });
+        println!("Serving metrics on http://{}/metrics", addr);
+    }
+    Ok(())
+}
+
+/// Runs `bulk get|insert <path|-> [--format text|csv|tsv|jsonl] [--lenient]
+/// [--comments]`. `--comments` skips lines starting with `#`.
+async fn run_bulk(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let (operation, path) = match args {
+        [operation, path, ..] if operation == "get" || operation == "insert" => (operation, path),
+        _ => {
+            eprintln!("Usage: bulk get|insert <path|-> [--format text|csv|tsv|jsonl] [--lenient] [--comments]");
+            return Ok(2);
+        }
+    };
+    let format = match flag_or_env(&args[2..], "--format", "INPUT_FORMAT") {
+        Some(format) => Some(reader::Format::parse(&format)?),
+        None => None,
+    };
+    let mode = if args.iter().any(|arg| arg == "--lenient") {
+        reader::Mode::Lenient
+    } else {
+        reader::Mode::Strict
+    };
+    let comments = args.iter().any(|arg| arg == "--comments");
+
+    let session = connect().await?;
+    let mut three_grams = reader::open(path, format, mode, comments)?;
+    if operation == "get" {
+        let inputs = three_grams
+            .by_ref()
+            .map(|three_gram| three_gram.map(|three_gram| three_gram.input()));
+        let (run_id, count) = query_3_grams::get_bulk(&session, inputs).await?;
+        println!("Run ID: {}", run_id);
+        println!("Queried {} three-grams", count);
+    } else {
+        let (run_id, count) = query_3_grams::insert_bulk(&session, three_grams.by_ref()).await?;
+        println!("Run ID: {}", run_id);
+        println!("Inserted {} three-grams", count);
+    }
+    reader::print_skipped(three_grams.errors());
+    Ok(0)
+}
+
+/// Runs `ngram get|insert <words...>`, `ngram insert-text <text...>` and
+/// `ngram schema [--apply]` for the configured N-gram sizes.
+async fn run_n_gram(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("get") if args.len() > 1 => {
+            let session = connect().await?;
+            let input = n_gram_specs::NGramInput::new(args[1..].to_vec());
+            let result = query_n_grams::get(&session, &input).await?;
+            println!("{:?}", result);
+            Ok(0)
+        }
+        Some("insert") if args.len() > 1 => {
+            let session = connect().await?;
+            let input = n_gram_specs::NGramInput::new(args[1..].to_vec());
+            let result = query_n_grams::insert(&session, &input).await?;
+            println!("{:?}", result);
+            Ok(0)
+        }
+        Some("insert-text") if args.len() > 1 => {
+            let session = connect().await?;
+            let count = query_n_grams::insert_text(&session, &args[1..].join(" ")).await?;
+            println!("Inserted {} n-grams", count);
+            Ok(0)
+        }
+        Some("schema") => {
+            if args.iter().any(|arg| arg == "--apply") {
+                let session = connect().await?;
+                schema::create(&session, corpus::active()).await?;
+                println!("Created the tables for {:?}-grams", schema::configured());
+            } else {
+                for cql in schema::create_statements(corpus::active()) {
+                    println!("{};", cql);
+                }
+            }
+            Ok(0)
+        }
+        _ => {
+            eprintln!("Usage: ngram get|insert <words...>");
+            eprintln!("       ngram insert-text <text...>");
+            eprintln!("       ngram schema [--apply]");
+            Ok(2)
+        }
+    }
+}
+
+/// Runs `corpus list`, `corpus create <name> [--replication-factor <n>]` and
+/// `corpus compare <word_1> <word_2> <word_3> [--corpora <name,...>]`, the
+/// latter comparing the frequency of a 3-gram across all or the given corpora.
+async fn run_corpus(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("list") => {
+            let session = open_session().await?;
+            for corpus in corpus::list(&session).await? {
+                let marker = if &corpus == corpus::active() {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{} {} (keyspace {})", marker, corpus.name, corpus.keyspace);
+            }
+            Ok(0)
+        }
+        Some("create") if args.len() > 1 && !args[1].starts_with("--") => {
+            let corpus = Corpus::parse(&args[1])?;
+            let replication_factor =
+                match flag_or_env(&args[2..], "--replication-factor", "REPLICATION_FACTOR") {
+                    Some(value) => value
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|value| *value > 0)
+                        .ok_or(format!("Invalid replication factor {}", value))?,
+                    None => 1,
+                };
+            let session = open_session().await?;
+            corpus::create(&session, &corpus, replication_factor).await?;
+            println!(
+                "Created corpus {} in keyspace {}",
+                corpus.name, corpus.keyspace
+            );
+            Ok(0)
+        }
+        Some("compare") if args.len() > 3 => {
+            let input = query_3_grams::specs::ThreeGramInput::new(
+                args[1].clone(),
+                args[2].clone(),
+                args[3].clone(),
+            );
+            let input = normalize::active().normalize_input(&input)?;
+            let session = open_session().await?;
+            let corpora = match flag_or_env(&args[4..], "--corpora", "CORPORA") {
+                Some(names) => names
+                    .split(',')
+                    .map(|name| Corpus::parse(name.trim()))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => corpus::list(&session).await?,
+            };
+            println!("{} {} {}", input.word_1, input.word_2, input.word_3);
+            for corpus in &corpora {
+                match query_3_grams::get_freq_in(&session, corpus, &input).await {
+                    Ok(freq) => println!(" {}: {}", corpus.name, freq),
+                    Err(err) => println!(" {}: {}", corpus.name, err),
+                }
+            }
+            Ok(0)
+        }
+        _ => {
+            eprintln!("Usage: corpus list");
+            eprintln!("       corpus create <name> [--replication-factor <n>]");
+            eprintln!("       corpus compare <word_1> <word_2> <word_3> [--corpora <name,...>]");
+            Ok(2)
+        }
+    }
+}
+
+/// Runs `trend series <word_1> <word_2> <word_3> [--granularity
+/// day|month|year] [--from <bucket>] [--to <bucket>]` and `trend compare
+/// <bucket_a> <bucket_b> [--limit <n>]` on the counts kept with `--buckets`.
+async fn run_trend(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    match args.first().map(|arg| arg.as_str()) {
+        Some("series") if args.len() > 3 => {
+            let input = query_3_grams::specs::ThreeGramInput::new(
+                args[1].clone(),
+                args[2].clone(),
+                args[3].clone(),
+            );
+            let input = normalize::active().normalize_input(&input)?;
+            let granularity = trends::Granularity::parse(
+                &flag_value(args, "--granularity").unwrap_or_else(|| String::from("month")),
+            )?;
+            let from = flag_value(args, "--from");
+            let to = flag_value(args, "--to");
+            let session = connect().await?;
+            let series = trends::series(
+                &session,
+                &input,
+                granularity,
+                from.as_deref(),
+                to.as_deref(),
+            )
+            .await?;
+            println!(
+                "{} {} {} per {}:",
+                input.word_1,
+                input.word_2,
+                input.word_3,
+                granularity.name()
+            );
+            for (bucket, freq) in series {
+                println!(" {}: {}", bucket, freq);
+            }
+            Ok(0)
+        }
+        Some("compare") if args.len() > 2 => {
+            let limit = match flag_value(args, "--limit") {
+                Some(limit) => limit
+                    .parse::<usize>()
+                    .map_err(|_| "--limit expects a number")?,
+                None => 10,
+            };
+            let session = connect().await?;
+            let (rising, declining) = trends::trending(&session, &args[1], &args[2], limit).await?;
+            trends::print_trends(
+                &format!("Trending from {} to {}", args[1], args[2]),
+                &rising,
+            );
+            trends::print_trends(
+                &format!("Declining from {} to {}", args[1], args[2]),
+                &declining,
+            );
+            Ok(0)
+        }
+        _ => {
+            eprintln!("Usage: trend series <word_1> <word_2> <word_3> [--granularity day|month|year] [--from <bucket>] [--to <bucket>]");
+            eprintln!("       trend compare <bucket_a> <bucket_b> [--limit <n>]");
+            Ok(2)
+        }
+    }
+}
+
+/// Runs `top [<n>] [--word <word>] [--min-length <n>]` over the leaderboard
+/// and `top rebuild [--size <n>] [--parallelism <n>]`.
+async fn run_top(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let number = |flag: &str, default: usize| -> Result<usize, String> {
+        match flag_value(args, flag) {
+            Some(value) => value
+                .parse::<usize>()
+                .map_err(|_| format!("{} expects a number", flag)),
+            None => Ok(default),
+        }
+    };
+    if args.first().map(|arg| arg.as_str()) == Some("rebuild") {
+        let size = number("--size", 10000)?;
+        let parallelism = number("--parallelism", 8)?.max(1);
+        let session = connect().await?;
+        let count = leaderboard::rebuild(session, size, parallelism).await?;
+        println!("Rebuilt the leaderboard with {} three-grams", count);
+        return Ok(0);
+    }
+
+    let limit = match args.first().filter(|arg| !arg.starts_with("--")) {
+        Some(limit) => match limit.parse::<usize>() {
+            Ok(limit) => limit,
+            Err(_) => {
+                eprintln!("Usage: top [<n>] [--word <word>] [--min-length <n>]");
+                eprintln!("       top rebuild [--size <n>] [--parallelism <n>]");
+                return Ok(2);
+            }
+        },
+        None => 20,
+    };
+    let filter = leaderboard::TopFilter {
+        word: flag_value(args, "--word").map(|word| normalize::active().normalize(&word)),
+        min_length: number("--min-length", 0)?,
+    };
+    let session = connect().await?;
+    for three_gram in leaderboard::top(&session, limit, &filter).await? {
+        println!(
+            "{} {} {}: {}",
+            three_gram.word_1, three_gram.word_2, three_gram.word_3, three_gram.freq
+        );
+    }
+    Ok(0)
+}
+
+/// Runs a non-interactive command given on the command line and returns the
+/// process exit code.
+pub async fn run(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    match args[0].as_str() {
+        "repl" => {
+            let session = connect().await?;
+            spawn_metrics_server(&args[1..])?;
+            while crate::run_menu(&session).await? {
+                println!();
+            }
+            Ok(0)
+        }
+        "serve" => {
+            let addr = flag_or_env(&args[1..], "--addr", "SERVE_ADDR")
+                .unwrap_or_else(|| String::from("127.0.0.1:8080"));
+            let addr: SocketAddr = addr
+                .parse()
+                .map_err(|err| format!("Invalid address {}: {}", addr, err))?;
+            let session = connect().await?;
+            spawn_metrics_server(&args[1..])?;
+            server::serve(session, addr)
+                .await
+                .map_err(|err| err as Box<dyn Error>)?;
+            Ok(0)
+        }
+        "grpc" => {
+            let addr = flag_or_env(&args[1..], "--addr", "GRPC_ADDR")
+                .unwrap_or_else(|| String::from("127.0.0.1:50051"));
+            let addr: SocketAddr = addr
+                .parse()
+                .map_err(|err| format!("Invalid address {}: {}", addr, err))?;
+            let session = connect().await?;
+            spawn_metrics_server(&args[1..])?;
+            grpc::serve(session, addr)
+                .await
+                .map_err(|err| err as Box<dyn Error>)?;
+            Ok(0)
+        }
+        "bulk" => run_bulk(&args[1..]).await,
+        "ngram" => run_n_gram(&args[1..]).await,
+        "corpus" => run_corpus(&args[1..]).await,
+        "trend" => run_trend(&args[1..]).await,
+        "marginals" => match args.get(1).map(|arg| arg.as_str()) {
+            Some("rebuild") => {
+                let parallelism = match flag_value(&args[2..], "--parallelism") {
+                    Some(value) => value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|value| *value > 0)
+                        .ok_or("--parallelism expects a positive number")?,
+                    None => 8,
+                };
+                let session = connect().await?;
+                let count = marginals::rebuild(session, parallelism).await?;
+                println!("Rebuilt the marginals of {} three-grams", count);
+                Ok(0)
+            }
+            _ => {
+                eprintln!("Usage: marginals rebuild [--parallelism <n>]");
+                Ok(2)
+            }
+        },
+        "top" => run_top(&args[1..]).await,
+        "generate" => {
+            let options = generate::GenerateOptions::from(&args[1..])?;
+            let session = connect().await?;
+            let generated = generate::generate(&session, &options).await?;
+            println!("{}", generated.words.join(" "));
+            println!("({})", generated.stop.describe());
+            Ok(0)
+        }
+        "check" => {
+            let options = check::CheckOptions::from(&args[1..])?;
+            let session = connect().await?;
+            let suspicions = check::check(&session, &options).await?;
+            check::print(&suspicions);
+            Ok(0)
+        }
+        "cloze" => {
+            let options = cloze::ClozeOptions::from(&args[1..])?;
+            let session = connect().await?;
+            let (windows, candidates) = cloze::solve(&session, &options).await?;
+            cloze::print(&windows, &candidates);
+            Ok(0)
+        }
+        "summary" => {
+            let options = summary::SummaryOptions::from(&args[1..])?;
+            let session = connect().await?;
+            let summary = summary::summary(session, &options).await?;
+            summary::print(&summary, options.format)?;
+            Ok(0)
+        }
+        "collocations" => {
+            let options = collocations::CollocationOptions::from(&args[1..])?;
+            let session = connect().await?;
+            collocations::collocations(&session, &options).await?;
+            Ok(0)
+        }
+        "compare" => {
+            let options = compare::CompareOptions::from(&args[1..])?;
+            let session = open_session().await?;
+            compare::compare(session, &options).await?;
+            Ok(0)
+        }
+        "export" => {
+            let options = export::ExportOptions::from(&args[1..])?;
+            let session = connect().await?;
+            export::export(session, &options).await?;
+            Ok(0)
+        }
+        "import" => {
+            let options = import::ImportOptions::from(&args[1..])?;
+            let session = connect().await?;
+            import::import(&session, &options).await?;
+            Ok(0)
+        }
+        "import-google-books" => {
+            let options = google_books::GoogleBooksOptions::from(&args[1..])?;
+            let session = connect().await?;
+            google_books::import(&session, &options).await?;
+            Ok(0)
+        }
+        "stats" => {
+            match args.get(1).map(|arg| arg.as_str()) {
+                Some("diff") => stats::diff::run_diff(&args[2..]),
+                Some("select") => {
+                    let filter = stats::filter::StatsFilter::from(&args[2..])?;
+                    stats::create_select_stats(&filter)?;
+                    Ok(0)
+                }
+                Some("insert") => {
+                    let filter = stats::filter::StatsFilter::from(&args[2..])?;
+                    stats::create_insert_stats(&filter)?;
+                    Ok(0)
+                }
+                Some("export-prometheus") => {
+                    let operation = args.get(2).ok_or("Expected select or insert")?;
+                    let filter = stats::filter::StatsFilter::from(&args[3..])?;
+                    stats::export_prometheus(operation, &filter)?;
+                    Ok(0)
+                }
+                _ => {
+                    eprintln!("Usage: stats select|insert [--from <time>] [--to <time>] [--pattern <glob>] [--run-id <id>] [--bucket <seconds>]");
+                    eprintln!("       stats export-prometheus select|insert [filters]");
+                    eprintln!("       stats diff <run-a> <run-b> [--threshold <percent>] [--alpha <level>]");
+                    Ok(2)
+                }
+            }
+        }
+        command => {
+            eprintln!("Unknown command: {}", command);
+            Ok(2)
+        }
+    }
+}
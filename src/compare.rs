@@ -0,0 +1,318 @@
+use crate::corpus::{self, Corpus};
+use crate::normalize;
+use crate::query_3_grams::{self, specs};
+use itertools::Itertools;
+use scylla::Session;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+static DEFAULT_LIMIT: usize = 10;
+static WILDCARD: &str = "_";
+
+pub struct CompareOptions {
+    pub corpus_a: Corpus,
+    pub corpus_b: Corpus,
+    pub words: [String; 3],
+    pub limit: usize,
+    pub recount: bool,
+}
+
+/// How much more (or less) often something occurs in corpus A than in B.
+pub struct Keyness {
+    pub log_likelihood: f64,
+    pub chi_square: f64,
+}
+
+/// The counts of one 3-gram or continuation in both corpora.
+struct Row {
+    label: String,
+    freq_a: i64,
+    freq_b: i64,
+    keyness: Keyness,
+}
+
+/// One cell of Dunning's log-likelihood, `observed * ln(observed / expected)`,
+/// 0 for an empty cell.
+pub fn ll_term(observed: f64, expected: f64) -> f64 {
+    if observed <= 0.0 || expected <= 0.0 {
+        0.0
+    } else {
+        observed * (observed / expected).ln()
+    }
+}
+
+pub fn per_million(freq: i64, total: i64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        freq as f64 * 1_000_000.0 / total as f64
+    }
+}
+
+/// Dunning's log-likelihood and Pearson's chi-square of the 2x2 table of
+/// `freq` against the rest of each corpus.
+pub fn keyness(freq_a: i64, total_a: i64, freq_b: i64, total_b: i64) -> Keyness {
+    let (a, b) = (freq_a as f64, freq_b as f64);
+    let (c, d) = ((total_a - freq_a) as f64, (total_b - freq_b) as f64);
+    let total = (total_a + total_b) as f64;
+    if total == 0.0 {
+        return Keyness {
+            log_likelihood: 0.0,
+            chi_square: 0.0,
+        };
+    }
+
+    let expected_a = total_a as f64 * (a + b) / total;
+    let expected_b = total_b as f64 * (a + b) / total;
+    let log_likelihood = 2.0 * (ll_term(a, expected_a) + ll_term(b, expected_b));
+
+    let denominator = (a + b) * (c + d) * (a + c) * (b + d);
+    let chi_square = if denominator == 0.0 {
+        0.0
+    } else {
+        total * (a * d - b * c).powi(2) / denominator
+    };
+    Keyness {
+        log_likelihood,
+        chi_square,
+    }
+}
+
+impl CompareOptions {
+    /// Parses `<corpus_a> <corpus_b> <word_1> <word_2> <word_3> [--limit <n>]
+    /// [--recount]`. One word may be `_` to compare a pair pattern instead of
+    /// a 3-gram.
+    pub fn from(args: &[String]) -> Result<CompareOptions, String> {
+        let mut positional = Vec::new();
+        let mut limit = DEFAULT_LIMIT;
+        let mut recount = false;
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--limit" => {
+                    limit = iter
+                        .next()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .ok_or("--limit expects a number")?
+                }
+                "--recount" => recount = true,
+                _ if !arg.starts_with("--") => positional.push(arg.clone()),
+                _ => return Err(format!("Unknown compare option: {}", arg)),
+            }
+        }
+
+        if positional.len() != 5 {
+            return Err(String::from("Usage: compare <corpus_a> <corpus_b> <word_1> <word_2> <word_3> [--limit <n>] [--recount] (one word may be _)"));
+        }
+        let wildcards = positional[2..]
+            .iter()
+            .filter(|word| word.as_str() == WILDCARD)
+            .count();
+        if wildcards > 1 {
+            return Err(String::from("At most one word may be _"));
+        }
+        let corpus_a = Corpus::parse(&positional[0])?;
+        let corpus_b = Corpus::parse(&positional[1])?;
+        if corpus_a == corpus_b {
+            return Err(String::from("Expected two different corpora"));
+        }
+        let pipeline = normalize::active();
+        let words = [2, 3, 4].map(|index| {
+            let word = &positional[index];
+            if word == WILDCARD {
+                word.clone()
+            } else {
+                pipeline.normalize(word)
+            }
+        });
+        Ok(CompareOptions {
+            corpus_a,
+            corpus_b,
+            words,
+            limit,
+            recount,
+        })
+    }
+
+    /// The pair table answering the pattern and the pair it is keyed by. A
+    /// complete 3-gram is compared with the continuations of its first two
+    /// words.
+    fn pattern(&self) -> (specs::PairTable, specs::WordPair) {
+        let [word_1, word_2, word_3] = self.words.clone();
+        if word_1 == WILDCARD {
+            (
+                specs::PairTable::SecondThird,
+                specs::WordPair::new(word_2, word_3),
+            )
+        } else if word_2 == WILDCARD {
+            (
+                specs::PairTable::FirstThird,
+                specs::WordPair::new(word_1, word_3),
+            )
+        } else {
+            (
+                specs::PairTable::FirstSecond,
+                specs::WordPair::new(word_1, word_2),
+            )
+        }
+    }
+
+    fn is_pattern(&self) -> bool {
+        self.words.iter().any(|word| word == WILDCARD)
+    }
+}
+
+impl Row {
+    fn new(label: String, freq_a: i64, total_a: i64, freq_b: i64, total_b: i64) -> Row {
+        Row {
+            label,
+            freq_a,
+            freq_b,
+            keyness: keyness(freq_a, total_a, freq_b, total_b),
+        }
+    }
+
+    fn print(&self, total_a: i64, total_b: i64) {
+        println!(
+            " {}: {} ({:.2} pm) vs {} ({:.2} pm), log-likelihood {:.2}, chi-square {:.2}",
+            self.label,
+            self.freq_a,
+            per_million(self.freq_a, total_a),
+            self.freq_b,
+            per_million(self.freq_b, total_b),
+            self.keyness.log_likelihood,
+            self.keyness.chi_square
+        );
+    }
+}
+
+fn print_over_represented(rows: &[&Row], corpus: &Corpus, total_a: i64, total_b: i64) {
+    println!("Over-represented in {}:", corpus.name);
+    if rows.is_empty() {
+        println!(" (none)");
+    }
+    for row in rows {
+        row.print(total_a, total_b);
+    }
+}
+
+/// Prints the frequencies of a 3-gram or pair pattern in two corpora per
+/// million tokens with their keyness, followed by the continuations that are
+/// most over-represented in either corpus.
+pub async fn compare(session: Session, options: &CompareOptions) -> Result<(), Box<dyn Error>> {
+    let session = Arc::new(session);
+    let (corpus_a, corpus_b) = (&options.corpus_a, &options.corpus_b);
+    let total_a = corpus::three_gram_total(session.clone(), corpus_a, options.recount).await?;
+    let total_b = corpus::three_gram_total(session.clone(), corpus_b, options.recount).await?;
+
+    let (pair_table, word_pair) = options.pattern();
+    let map_a = query_3_grams::get_pair_in(&session, corpus_a, pair_table, word_pair.clone())
+        .await?
+        .word_pair_map;
+    let map_b = query_3_grams::get_pair_in(&session, corpus_b, pair_table, word_pair)
+        .await?
+        .word_pair_map;
+
+    println!(
+        "Comparing {} ({} tokens) with {} ({} tokens)",
+        corpus_a.name, total_a, corpus_b.name, total_b
+    );
+    let label = options.words.join(" ");
+    let summary = if options.is_pattern() {
+        let sum = |map: &HashMap<String, i32>| map.values().map(|freq| *freq as i64).sum();
+        Row::new(label, sum(&map_a), total_a, sum(&map_b), total_b)
+    } else {
+        let word_3 = &options.words[2];
+        let freq = |map: &HashMap<String, i32>| map.get(word_3).copied().unwrap_or(0) as i64;
+        Row::new(label, freq(&map_a), total_a, freq(&map_b), total_b)
+    };
+    summary.print(total_a, total_b);
+
+    let rows: Vec<Row> = map_a
+        .keys()
+        .chain(map_b.keys())
+        .unique()
+        .map(|word| {
+            let freq_a = map_a.get(word).copied().unwrap_or(0) as i64;
+            let freq_b = map_b.get(word).copied().unwrap_or(0) as i64;
+            Row::new(word.clone(), freq_a, total_a, freq_b, total_b)
+        })
+        .sorted_by(|a, b| {
+            b.keyness
+                .log_likelihood
+                .total_cmp(&a.keyness.log_likelihood)
+                .then_with(|| a.label.cmp(&b.label))
+        })
+        .collect();
+    let over_a: Vec<&Row> = rows
+        .iter()
+        .filter(|row| per_million(row.freq_a, total_a) > per_million(row.freq_b, total_b))
+        .take(options.limit)
+        .collect();
+    let over_b: Vec<&Row> = rows
+        .iter()
+        .filter(|row| per_million(row.freq_b, total_b) > per_million(row.freq_a, total_a))
+        .take(options.limit)
+        .collect();
+
+    println!("\nContinuations ({} distinct)", rows.len());
+    print_over_represented(&over_a, corpus_a, total_a, total_b);
+    print_over_represented(&over_b, corpus_b, total_a, total_b);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ll_term_is_zero_for_empty_cells() {
+        assert_eq!(ll_term(0.0, 5.0), 0.0);
+        assert_eq!(ll_term(5.0, 0.0), 0.0);
+        assert_eq!(ll_term(5.0, 5.0), 0.0);
+        assert!((ll_term(10.0, 5.0) - 10.0 * 2.0_f64.ln()).abs() < 1e-12);
+        assert!(ll_term(5.0, 10.0) < 0.0);
+    }
+
+    #[test]
+    fn per_million_handles_an_empty_corpus() {
+        assert_eq!(per_million(5, 1_000_000), 5.0);
+        assert_eq!(per_million(1, 4_000_000), 0.25);
+        assert_eq!(per_million(5, 0), 0.0);
+    }
+
+    #[test]
+    fn keyness_of_equal_rates_is_zero() {
+        let keyness = keyness(10, 1000, 20, 2000);
+        assert!(keyness.log_likelihood.abs() < 1e-9);
+        assert!(keyness.chi_square.abs() < 1e-9);
+    }
+
+    #[test]
+    fn keyness_matches_the_2x2_table() {
+        // a = 30 of 1000, b = 10 of 1000: expected 20 in each corpus.
+        let keyness = keyness(30, 1000, 10, 1000);
+        let log_likelihood = 2.0 * (30.0 * 1.5_f64.ln() + 10.0 * 0.5_f64.ln());
+        assert!((keyness.log_likelihood - log_likelihood).abs() < 1e-9);
+        let chi_square =
+            2000.0 * (30.0 * 990.0 - 10.0 * 970.0_f64).powi(2) / (40.0 * 1960.0 * 1000.0 * 1000.0);
+        assert!((keyness.chi_square - chi_square).abs() < 1e-9);
+    }
+
+    #[test]
+    fn keyness_handles_zero_counts() {
+        let unseen_in_b = keyness(5, 1000, 0, 1000);
+        assert!(unseen_in_b.log_likelihood > 0.0 && unseen_in_b.log_likelihood.is_finite());
+        assert!(unseen_in_b.chi_square > 0.0 && unseen_in_b.chi_square.is_finite());
+
+        let unseen = keyness(0, 1000, 0, 1000);
+        assert_eq!(unseen.log_likelihood, 0.0);
+        assert_eq!(unseen.chi_square, 0.0);
+
+        let empty = keyness(0, 0, 0, 0);
+        assert_eq!(empty.log_likelihood, 0.0);
+        assert_eq!(empty.chi_square, 0.0);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::leaderboard;
+use crate::marginals;
+use crate::query_3_grams::{scan, statements};
+use crate::query_n_grams::schema;
+use scylla::{IntoTypedRows, Session};
+use std::error::Error;
+use std::sync::{Arc, OnceLock};
+
+static ACTIVE: OnceLock<Corpus> = OnceLock::new();
+static DEFAULT_NAME: &str = "default";
+static SCAN_PARALLELISM: usize = 8;
+static KEYSPACE_PREFIX: &str = "n_grams";
+/// Scylla limits keyspace names to 48 characters, `n_grams_` included.
+static MAX_NAME_LENGTH: usize = 48 - "n_grams_".len();
+
+/// A named set of n-gram tables. Every corpus has its own keyspace: the
+/// default one is `n_grams`, any other `n_grams_<name>`, so that several
+/// corpora can be queried side by side from one session.
+#[derive(Clone, PartialEq)]
+pub struct Corpus {
+    pub name: String,
+    pub keyspace: String,
+}
+
+impl Default for Corpus {
+    fn default() -> Corpus {
+        Corpus {
+            name: DEFAULT_NAME.to_string(),
+            keyspace: KEYSPACE_PREFIX.to_string(),
+        }
+    }
+}
+
+impl Corpus {
+    /// Corpus names are lowercase letters, digits and underscores.
+    pub fn parse(name: &str) -> Result<Corpus, String> {
+        if name == DEFAULT_NAME {
+            return Ok(Corpus::default());
+        }
+        let valid = name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if name.is_empty() || !valid || name.len() > MAX_NAME_LENGTH {
+            return Err(format!(
+                "Invalid corpus name \"{}\" (expected up to {} lowercase letters, digits or underscores)",
+                name, MAX_NAME_LENGTH
+            ));
+        }
+        Ok(Corpus {
+            name: name.to_string(),
+            keyspace: format!("{}_{}", KEYSPACE_PREFIX, name),
+        })
+    }
+
+    /// The corpus stored in `keyspace`, if it is one.
+    fn from_keyspace(keyspace: &str) -> Option<Corpus> {
+        if keyspace == KEYSPACE_PREFIX {
+            return Some(Corpus::default());
+        }
+        let name = keyspace.strip_prefix(KEYSPACE_PREFIX)?.strip_prefix('_')?;
+        Corpus::parse(name).ok()
+    }
+
+    /// Qualifies `table` with the keyspace of the corpus.
+    pub fn table(&self, table: &str) -> String {
+        format!("{}.{}", self.keyspace, table)
+    }
+}
+
+/// Sets the process-wide corpus from `--corpus <name>` or the `CORPUS`
+/// environment variable and returns `args` without the flag.
+pub fn init(args: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut remaining = Vec::new();
+    let mut name = std::env::var("CORPUS").ok();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--corpus" {
+            name = Some(iter.next().ok_or("--corpus expects a name")?);
+        } else {
+            remaining.push(arg);
+        }
+    }
+
+    let corpus = Corpus::parse(name.as_deref().unwrap_or(DEFAULT_NAME))?;
+    _ = ACTIVE.set(corpus);
+    Ok(remaining)
+}
+
+pub fn active() -> &'static Corpus {
+    ACTIVE.get_or_init(Corpus::default)
+}
+
+/// Qualifies `table` with the keyspace of the active corpus.
+pub fn table(table: &str) -> String {
+    active().table(table)
+}
+
+/// Every corpus of the cluster, sorted by name.
+pub async fn list(session: &Session) -> Result<Vec<Corpus>, Box<dyn Error>> {
+    let rows = session
+        .query("SELECT keyspace_name FROM system_schema.keyspaces", ())
+        .await?
+        .rows
+        .unwrap_or_default();
+    let mut corpora = Vec::new();
+    for row in rows.into_typed::<(String,)>() {
+        let (keyspace,) = row?;
+        if let Some(corpus) = Corpus::from_keyspace(&keyspace) {
+            corpora.push(corpus);
+        }
+    }
+    corpora.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(corpora)
+}
+
+/// Creates the keyspace of `corpus` with the 3-gram tables and the tables of
+/// the configured N-gram sizes. The normalization pipeline is recorded on
+/// first use, like for the default corpus.
+pub async fn create(
+    session: &Session,
+    corpus: &Corpus,
+    replication_factor: u32,
+) -> Result<(), Box<dyn Error>> {
+    session
+        .query(
+            format!(
+                "CREATE KEYSPACE IF NOT EXISTS {} WITH replication = {{'class': 'SimpleStrategy', 'replication_factor': {}}}",
+                corpus.keyspace, replication_factor
+            ),
+            (),
+        )
+        .await?;
+    session
+        .query(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {} (key text PRIMARY KEY, value text)",
+                corpus.table("metadata")
+            ),
+            (),
+        )
+        .await?;
+    marginals::create(session, corpus).await?;
+    leaderboard::create(session, corpus).await?;
+    let mut sizes = schema::configured().to_vec();
+    if !sizes.contains(&3) {
+        sizes.push(3);
+    }
+    for table in sizes.iter().flat_map(|n| schema::tables(*n)) {
+        session.query(table.create_cql(corpus), ()).await?;
+    }
+    Ok(())
+}
+
+/// Reads `key` from the metadata table of `corpus`.
+pub async fn get_metadata(
+    session: &Session,
+    corpus: &Corpus,
+    key: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "SELECT value FROM {} WHERE key = ?",
+            corpus.table("metadata")
+        ),
+    )
+    .await?;
+    let row = session
+        .execute(&prepared, (key,))
+        .await?
+        .maybe_first_row_typed::<(String,)>()?;
+    Ok(row.map(|(value,)| value))
+}
+
+pub async fn set_metadata(
+    session: &Session,
+    corpus: &Corpus,
+    key: &str,
+    value: &str,
+) -> Result<(), Box<dyn Error>> {
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "INSERT INTO {} (key, value) VALUES (?, ?)",
+            corpus.table("metadata")
+        ),
+    )
+    .await?;
+    session.execute(&prepared, (key, value)).await?;
+    Ok(())
+}
+
+/// The sum of all 3-gram frequencies of `corpus`, which is about the number
+/// of tokens it was built from. Read from the total the marginals maintain on
+/// every write; `recount` pages through the whole corpus instead.
+pub async fn three_gram_total(
+    session: Arc<Session>,
+    corpus: &Corpus,
+    recount: bool,
+) -> Result<i64, Box<dyn Error>> {
+    if !recount {
+        return marginals::total_in(&session, corpus).await;
+    }
+
+    let mut total: i64 = 0;
+    let mut batches = scan::scan_corpus(session.clone(), corpus.clone(), SCAN_PARALLELISM);
+    while let Some(batch) = batches.recv().await {
+        total += batch?
+            .iter()
+            .map(|three_gram| three_gram.freq as i64)
+            .sum::<i64>();
+    }
+    Ok(total)
+}
@@ -0,0 +1,384 @@
+use crate::query_3_grams::{scan, specs};
+use flate2::write::GzEncoder;
+use scylla::Session;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+use std::time::Instant;
+
+static DEFAULT_PARALLELISM: usize = 8;
+static CSV_HEADER: [&str; 4] = ["word_1", "word_2", "word_3", "freq"];
+
+/// Written at the start of binary snapshots. Each record follows as three
+/// words (little-endian `u16` length, then UTF-8 bytes) and a little-endian
+/// `i32` frequency.
+pub static BINARY_MAGIC: &[u8; 4] = b"3GB1";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Jsonl,
+    Binary,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+pub struct ExportOptions {
+    pub path: String,
+    pub format: Format,
+    pub compression: Compression,
+    pub min_freq: i32,
+    pub prefix: Option<String>,
+    pub parallelism: usize,
+}
+
+enum Output {
+    Plain(BufWriter<Box<dyn Write>>),
+    Gzip(GzEncoder<BufWriter<Box<dyn Write>>>),
+    Zstd(zstd::Encoder<'static, BufWriter<Box<dyn Write>>>),
+}
+
+enum RecordWriter {
+    Csv(Box<csv::Writer<Output>>),
+    Jsonl(Output),
+    Binary(Output),
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Format, String> {
+        match name {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::Jsonl),
+            "binary" => Ok(Format::Binary),
+            _ => Err(format!("Unknown snapshot format: {}", name)),
+        }
+    }
+
+    /// Picks the format from the file name, ignoring a compression extension.
+    /// Defaults to `Csv`.
+    pub fn detect(path: &str) -> Format {
+        let path = path.trim_end_matches(".gz").trim_end_matches(".zst");
+        if path.ends_with(".jsonl") {
+            Format::Jsonl
+        } else if path.ends_with(".bin") {
+            Format::Binary
+        } else {
+            Format::Csv
+        }
+    }
+}
+
+impl Compression {
+    pub fn parse(name: &str) -> Result<Compression, String> {
+        match name {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!("Unknown compression: {}", name)),
+        }
+    }
+
+    pub fn detect(path: &str) -> Compression {
+        if path.ends_with(".gz") {
+            Compression::Gzip
+        } else if path.ends_with(".zst") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Parses `<path|-> [--format csv|jsonl|binary] [--compress none|gzip|zstd]
+    /// [--min-freq <n>] [--prefix <text>] [--parallelism <n>]`. Format and
+    /// compression default to what the file name suggests.
+    pub fn from(args: &[String]) -> Result<ExportOptions, String> {
+        let mut path = None;
+        let mut format = None;
+        let mut compression = None;
+        let mut min_freq = 1;
+        let mut prefix = None;
+        let mut parallelism = DEFAULT_PARALLELISM;
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--format" => {
+                    format = Some(Format::parse(
+                        iter.next().ok_or("--format expects a format")?,
+                    )?)
+                }
+                "--compress" => {
+                    compression = Some(Compression::parse(
+                        iter.next().ok_or("--compress expects a compression")?,
+                    )?)
+                }
+                "--min-freq" => {
+                    min_freq = iter
+                        .next()
+                        .and_then(|value| value.parse::<i32>().ok())
+                        .ok_or("--min-freq expects a number")?
+                }
+                "--prefix" => prefix = Some(iter.next().ok_or("--prefix expects a text")?.clone()),
+                "--parallelism" => {
+                    parallelism = iter
+                        .next()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .filter(|parallelism| *parallelism > 0)
+                        .ok_or("--parallelism expects a positive number")?
+                }
+                _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+                _ => return Err(format!("Unknown export option: {}", arg)),
+            }
+        }
+
+        let path = path.ok_or("Usage: export <path|-> [--format csv|jsonl|binary] [--compress none|gzip|zstd] [--min-freq <n>] [--prefix <text>] [--parallelism <n>]")?;
+        Ok(ExportOptions {
+            format: format.unwrap_or_else(|| Format::detect(&path)),
+            compression: compression.unwrap_or_else(|| Compression::detect(&path)),
+            path,
+            min_freq,
+            prefix,
+            parallelism,
+        })
+    }
+
+    /// Whether the 3-gram passes the `--min-freq` and `--prefix` filters. The
+    /// prefix is matched against "word_1 word_2 word_3".
+    fn matches(&self, three_gram: &specs::ThreeGram) -> bool {
+        if three_gram.freq < self.min_freq {
+            return false;
+        }
+        match &self.prefix {
+            Some(prefix) => format!(
+                "{} {} {}",
+                three_gram.word_1, three_gram.word_2, three_gram.word_3
+            )
+            .starts_with(prefix.as_str()),
+            None => true,
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(writer) => writer.write(buf),
+            Output::Gzip(writer) => writer.write(buf),
+            Output::Zstd(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(writer) => writer.flush(),
+            Output::Gzip(writer) => writer.flush(),
+            Output::Zstd(writer) => writer.flush(),
+        }
+    }
+}
+
+impl Output {
+    fn create(path: &str, compression: Compression) -> io::Result<Output> {
+        let writer: Box<dyn Write> = if path == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(path)?)
+        };
+        let writer = BufWriter::new(writer);
+        Ok(match compression {
+            Compression::None => Output::Plain(writer),
+            Compression::Gzip => {
+                Output::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+            }
+            Compression::Zstd => Output::Zstd(zstd::Encoder::new(writer, 0)?),
+        })
+    }
+
+    /// Writes the compression trailer and flushes everything to the file.
+    fn finish(self) -> io::Result<()> {
+        let mut writer = match self {
+            Output::Plain(writer) => writer,
+            Output::Gzip(writer) => writer.finish()?,
+            Output::Zstd(writer) => writer.finish()?,
+        };
+        writer.flush()
+    }
+}
+
+fn write_word(output: &mut Output, word: &str) -> Result<(), Box<dyn Error>> {
+    let length = u16::try_from(word.len())
+        .map_err(|_| format!("Word too long for the binary format: {}", word))?;
+    output.write_all(&length.to_le_bytes())?;
+    output.write_all(word.as_bytes())?;
+    Ok(())
+}
+
+impl RecordWriter {
+    fn new(format: Format, mut output: Output) -> Result<RecordWriter, Box<dyn Error>> {
+        Ok(match format {
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(output);
+                writer.write_record(CSV_HEADER)?;
+                RecordWriter::Csv(Box::new(writer))
+            }
+            Format::Jsonl => RecordWriter::Jsonl(output),
+            Format::Binary => {
+                output.write_all(BINARY_MAGIC)?;
+                RecordWriter::Binary(output)
+            }
+        })
+    }
+
+    fn write(&mut self, three_gram: &specs::ThreeGram) -> Result<(), Box<dyn Error>> {
+        match self {
+            RecordWriter::Csv(writer) => writer.write_record([
+                three_gram.word_1.as_str(),
+                three_gram.word_2.as_str(),
+                three_gram.word_3.as_str(),
+                three_gram.freq.to_string().as_str(),
+            ])?,
+            RecordWriter::Jsonl(output) => {
+                serde_json::to_writer(&mut *output, three_gram)?;
+                output.write_all(b"\n")?;
+            }
+            RecordWriter::Binary(output) => {
+                write_word(output, &three_gram.word_1)?;
+                write_word(output, &three_gram.word_2)?;
+                write_word(output, &three_gram.word_3)?;
+                output.write_all(&three_gram.freq.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        let output = match self {
+            RecordWriter::Csv(writer) => writer.into_inner().map_err(|err| err.into_error())?,
+            RecordWriter::Jsonl(output) | RecordWriter::Binary(output) => output,
+        };
+        output.finish()?;
+        Ok(())
+    }
+}
+
+/// Writes `three_grams` to a snapshot file the way `export` writes the
+/// tables, for tests reading snapshots back.
+#[cfg(test)]
+pub fn write_snapshot(
+    path: &str,
+    format: Format,
+    compression: Compression,
+    three_grams: &[specs::ThreeGram],
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = RecordWriter::new(format, Output::create(path, compression)?)?;
+    for three_gram in three_grams {
+        writer.write(three_gram)?;
+    }
+    writer.finish()
+}
+
+/// Writes every 3-gram passing the filters to the snapshot file and returns
+/// how many were written.
+pub async fn export(session: Session, options: &ExportOptions) -> Result<usize, Box<dyn Error>> {
+    let start_time = Instant::now();
+    let output = Output::create(&options.path, options.compression)
+        .map_err(|err| format!("Could not create {}: {}", options.path, err))?;
+    let mut writer = RecordWriter::new(options.format, output)?;
+    let mut batches = scan::scan_all(Arc::new(session), options.parallelism);
+
+    let mut count = 0;
+    while let Some(batch) = batches.recv().await {
+        for three_gram in batch? {
+            if options.matches(&three_gram) {
+                writer.write(&three_gram)?;
+                count += 1;
+            }
+        }
+    }
+    writer.finish()?;
+
+    if options.path != "-" {
+        println!(
+            "Exported {} three-grams to {} in {:.3} seconds",
+            count,
+            options.path,
+            start_time.elapsed().as_secs_f64()
+        );
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn snapshot(format: Format, three_grams: &[specs::ThreeGram]) -> Vec<u8> {
+        let path = std::env::temp_dir().join(format!("export-test-{}", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        write_snapshot(path, format, Compression::None, three_grams).unwrap();
+        let content = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+        content
+    }
+
+    fn three_grams() -> Vec<specs::ThreeGram> {
+        vec![
+            specs::ThreeGram::new("####".into(), "was".into(), "great".into(), 5),
+            specs::ThreeGram::new("a,b".into(), "ü".into(), "c".into(), 70000),
+        ]
+    }
+
+    #[test]
+    fn format_and_compression_are_detected_from_the_file_name() {
+        assert!(Format::detect("grams.jsonl.gz") == Format::Jsonl);
+        assert!(Format::detect("grams.bin.zst") == Format::Binary);
+        assert!(Format::detect("grams.txt") == Format::Csv);
+        assert!(Compression::detect("grams.csv.gz") == Compression::Gzip);
+        assert!(Compression::detect("grams.bin.zst") == Compression::Zstd);
+        assert!(Compression::detect("grams.csv") == Compression::None);
+    }
+
+    #[test]
+    fn binary_records_are_length_prefixed_words_and_a_frequency() {
+        let mut expected = BINARY_MAGIC.to_vec();
+        for (word, freq) in [(["####", "was", "great"], 5), (["a,b", "ü", "c"], 70000)] {
+            for word in word {
+                expected.extend((word.len() as u16).to_le_bytes());
+                expected.extend(word.as_bytes());
+            }
+            expected.extend(i32::to_le_bytes(freq));
+        }
+        assert_eq!(snapshot(Format::Binary, &three_grams()), expected);
+    }
+
+    #[test]
+    fn binary_rejects_words_longer_than_a_u16_length() {
+        let long = specs::ThreeGram::new("a".repeat(70000), "b".into(), "c".into(), 1);
+        let path = std::env::temp_dir().join(format!("export-test-{}", uuid::Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        assert!(write_snapshot(path, Format::Binary, Compression::None, &[long]).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn csv_and_jsonl_write_one_record_per_line() {
+        assert_eq!(
+            String::from_utf8(snapshot(Format::Csv, &three_grams())).unwrap(),
+            "word_1,word_2,word_3,freq\n####,was,great,5\n\"a,b\",ü,c,70000\n"
+        );
+        assert_eq!(
+            String::from_utf8(snapshot(Format::Jsonl, &three_grams())).unwrap(),
+            "{\"word_1\":\"####\",\"word_2\":\"was\",\"word_3\":\"great\",\"freq\":5}\n\
+             {\"word_1\":\"a,b\",\"word_2\":\"ü\",\"word_3\":\"c\",\"freq\":70000}\n"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use crate::normalize;
+use crate::query_3_grams::{self, specs};
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use scylla::Session;
+use std::error::Error;
+
+static DEFAULT_MAX_LENGTH: usize = 20;
+static DEFAULT_STOP: &str = ".";
+
+pub struct GenerateOptions {
+    pub word_1: String,
+    pub word_2: String,
+    pub max_length: usize,
+    pub temperature: f64,
+    pub top_k: usize,
+    pub top_p: f64,
+    pub seed: Option<u64>,
+    pub stop: Option<String>,
+}
+
+/// Why generation ended.
+pub enum Stop {
+    Token,
+    MaxLength,
+    NoContinuation,
+}
+
+pub struct Generated {
+    pub words: Vec<String>,
+    pub stop: Stop,
+}
+
+impl Stop {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Stop::Token => "reached the stop token",
+            Stop::MaxLength => "reached the maximum length",
+            Stop::NoContinuation => "no continuation of the last two words",
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: Option<&String>, flag: &str) -> Result<T, String> {
+    value
+        .and_then(|value| value.parse::<T>().ok())
+        .ok_or(format!("{} expects a number", flag))
+}
+
+impl GenerateOptions {
+    /// Parses `<word_1> <word_2> [--max-length <n>] [--temperature <t>]
+    /// [--top-k <k>] [--top-p <p>] [--seed <n>] [--stop <token>|--no-stop]`.
+    /// A temperature of 0 always picks the most frequent continuation, a top-k
+    /// of 0 keeps all of them.
+    pub fn from(args: &[String]) -> Result<GenerateOptions, String> {
+        let mut words = Vec::new();
+        let mut max_length = DEFAULT_MAX_LENGTH;
+        let mut temperature = 1.0;
+        let mut top_k = 0;
+        let mut top_p = 1.0;
+        let mut seed = None;
+        let mut stop = Some(DEFAULT_STOP.to_string());
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--max-length" => max_length = parse_number(iter.next(), arg)?,
+                "--temperature" => temperature = parse_number(iter.next(), arg)?,
+                "--top-k" => top_k = parse_number(iter.next(), arg)?,
+                "--top-p" => top_p = parse_number(iter.next(), arg)?,
+                "--seed" => seed = Some(parse_number(iter.next(), arg)?),
+                "--stop" => stop = Some(iter.next().ok_or("--stop expects a token")?.clone()),
+                "--no-stop" => stop = None,
+                _ if !arg.starts_with("--") => words.push(arg.clone()),
+                _ => return Err(format!("Unknown generate option: {}", arg)),
+            }
+        }
+
+        if words.len() != 2 {
+            return Err(String::from("Usage: generate <word_1> <word_2> [--max-length <n>] [--temperature <t>] [--top-k <k>] [--top-p <p>] [--seed <n>] [--stop <token>|--no-stop]"));
+        }
+        if temperature < 0.0 {
+            return Err(String::from("--temperature must not be negative"));
+        }
+        if !(top_p > 0.0 && top_p <= 1.0) {
+            return Err(String::from("--top-p must be in (0, 1]"));
+        }
+        let pipeline = normalize::active();
+        Ok(GenerateOptions {
+            word_1: pipeline.normalize(&words[0]),
+            word_2: pipeline.normalize(&words[1]),
+            max_length,
+            temperature,
+            top_k,
+            top_p,
+            seed,
+            stop: stop.map(|stop| pipeline.normalize(&stop)),
+        })
+    }
+
+    /// The continuations that may be sampled with their probabilities, most
+    /// likely first. Ties are broken by word, so a seed always reproduces the
+    /// same text.
+    fn distribution(&self, continuations: Vec<(String, i32)>) -> Vec<(String, f64)> {
+        let mut candidates: Vec<(String, i32)> = continuations
+            .into_iter()
+            .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+            .collect();
+        if self.temperature == 0.0 {
+            candidates.truncate(1);
+        } else if self.top_k > 0 {
+            candidates.truncate(self.top_k);
+        }
+
+        let temperature = if self.temperature == 0.0 {
+            1.0
+        } else {
+            self.temperature
+        };
+        // Scaled by the highest frequency first, so low temperatures can't
+        // overflow.
+        let max_freq = candidates.first().map_or(1, |(_, freq)| *freq) as f64;
+        let weights: Vec<f64> = candidates
+            .iter()
+            .map(|(_, freq)| (*freq as f64 / max_freq).powf(1.0 / temperature))
+            .collect();
+        let total: f64 = weights.iter().sum();
+        let mut distribution: Vec<(String, f64)> = candidates
+            .into_iter()
+            .zip(weights)
+            .map(|((word, _), weight)| (word, weight / total))
+            .collect();
+
+        let mut cumulative = 0.0;
+        let mut keep = 0;
+        for (_, probability) in &distribution {
+            keep += 1;
+            cumulative += probability;
+            if cumulative >= self.top_p {
+                break;
+            }
+        }
+        distribution.truncate(keep);
+        let total: f64 = distribution
+            .iter()
+            .map(|(_, probability)| probability)
+            .sum();
+        for (_, probability) in distribution.iter_mut() {
+            *probability /= total;
+        }
+        distribution
+    }
+}
+
+fn sample(distribution: &[(String, f64)], rng: &mut StdRng) -> String {
+    let mut target = rng.gen::<f64>();
+    for (word, probability) in distribution {
+        if target < *probability {
+            return word.clone();
+        }
+        target -= probability;
+    }
+    distribution[distribution.len() - 1].0.clone()
+}
+
+/// Extends the seed pair word by word, sampling each word from the
+/// continuations of the last two in `three_grams_1_2_pk`.
+pub async fn generate(
+    session: &Session,
+    options: &GenerateOptions,
+) -> Result<Generated, Box<dyn Error>> {
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut words = vec![options.word_1.clone(), options.word_2.clone()];
+
+    for _ in 0..options.max_length {
+        let word_pair = specs::WordPair::new(
+            words[words.len() - 2].clone(),
+            words[words.len() - 1].clone(),
+        );
+        let continuations =
+            query_3_grams::get_pair(session, specs::PairTable::FirstSecond, word_pair)
+                .await?
+                .word_pair_map
+                .into_iter()
+                .filter(|(_, freq)| *freq > 0)
+                .collect();
+        let distribution = options.distribution(continuations);
+        if distribution.is_empty() {
+            return Ok(Generated {
+                words,
+                stop: Stop::NoContinuation,
+            });
+        }
+
+        let word = sample(&distribution, &mut rng);
+        let is_stop = options.stop.as_ref() == Some(&word);
+        words.push(word);
+        if is_stop {
+            return Ok(Generated {
+                words,
+                stop: Stop::Token,
+            });
+        }
+    }
+    Ok(Generated {
+        words,
+        stop: Stop::MaxLength,
+    })
+}
@@ -0,0 +1,555 @@
+use crate::export::Compression;
+use crate::import::{self, Checkpoint, Pending, Strategy};
+use crate::normalize;
+use crate::query_3_grams::specs;
+use crate::reader::{self, LineError, Mode};
+use crate::trends::{self, Granularity};
+use scylla::Session;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufRead};
+use std::time::Instant;
+
+pub struct GoogleBooksOptions {
+    pub path: String,
+    pub compression: Compression,
+    pub from_year: Option<i32>,
+    pub to_year: Option<i32>,
+    pub per_year: bool,
+    pub strategy: Strategy,
+    pub checkpoint: Option<String>,
+    pub restart: bool,
+    pub mode: Mode,
+}
+
+/// All rows of one 3-gram within the year range. Google Books files list the
+/// years of an n-gram on consecutive lines.
+pub struct Group {
+    pub three_gram: specs::ThreeGram,
+    pub years: Vec<(i32, i32)>,
+    line_number: usize,
+    total: i64,
+}
+
+/// Reads `ngram TAB year TAB match_count TAB volume_count` lines and yields
+/// one group per 3-gram, with the words normalized by the active pipeline.
+/// N-grams with part-of-speech tags are skipped, as the dataset counts the
+/// same occurrences untagged as well.
+pub struct GoogleBooksReader {
+    lines: io::Lines<Box<dyn BufRead + Send>>,
+    line_number: usize,
+    from_year: Option<i32>,
+    to_year: Option<i32>,
+    mode: Mode,
+    errors: Vec<LineError>,
+    current: Option<(String, Group)>,
+    done: bool,
+}
+
+/// The part-of-speech tags of the Google Books n-grams.
+static POS_TAGS: [&str; 12] = [
+    "NOUN", "VERB", "ADJ", "ADV", "PRON", "DET", "ADP", "NUM", "CONJ", "PRT", "X", ".",
+];
+
+/// Counts beyond the range of the `freq` column are stored as `i32::MAX`.
+fn clamp(count: i64) -> i32 {
+    count.min(i32::MAX as i64) as i32
+}
+
+/// Whether `word` carries a part-of-speech tag, like `cat_NOUN`, or is a tag
+/// or marker itself, like `_NOUN_` or `_START_`.
+fn is_tagged(word: &str) -> bool {
+    if word.len() > 2 && word.starts_with('_') && word.ends_with('_') {
+        return true;
+    }
+    word.rsplit_once('_')
+        .is_some_and(|(stem, tag)| !stem.is_empty() && POS_TAGS.contains(&tag))
+}
+
+fn parse_row(line: &str) -> Result<(&str, i32, i64), String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 4 {
+        return Err(format!(
+            "expected 4 tab separated fields, found {}",
+            fields.len()
+        ));
+    }
+    let year = fields[1]
+        .trim()
+        .parse::<i32>()
+        .map_err(|_| format!("invalid year \"{}\"", fields[1]))?;
+    let match_count = fields[2]
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .filter(|count| *count >= 0)
+        .ok_or(format!("invalid match count \"{}\"", fields[2]))?;
+    Ok((fields[0], year, match_count))
+}
+
+impl GoogleBooksOptions {
+    /// Parses `<path|-> [--compress none|gzip|zstd] [--from-year <year>]
+    /// [--to-year <year>] [--per-year] [--strategy add|overwrite|max]
+    /// [--checkpoint <file>] [--restart] [--lenient]`. Counts are added by
+    /// default, since normalization can fold several n-grams into one.
+    pub fn from(args: &[String]) -> Result<GoogleBooksOptions, String> {
+        let mut path = None;
+        let mut compression = None;
+        let mut from_year = None;
+        let mut to_year = None;
+        let mut per_year = false;
+        let mut strategy = Strategy::Add;
+        let mut checkpoint = None;
+        let mut restart = false;
+        let mut mode = Mode::Strict;
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--compress" => {
+                    compression = Some(Compression::parse(
+                        iter.next().ok_or("--compress expects a compression")?,
+                    )?)
+                }
+                "--from-year" => {
+                    from_year = Some(
+                        iter.next()
+                            .and_then(|value| value.parse::<i32>().ok())
+                            .ok_or("--from-year expects a year")?,
+                    )
+                }
+                "--to-year" => {
+                    to_year = Some(
+                        iter.next()
+                            .and_then(|value| value.parse::<i32>().ok())
+                            .ok_or("--to-year expects a year")?,
+                    )
+                }
+                "--per-year" => per_year = true,
+                "--strategy" => {
+                    strategy = Strategy::parse(iter.next().ok_or("--strategy expects a strategy")?)?
+                }
+                "--checkpoint" => {
+                    checkpoint = Some(iter.next().ok_or("--checkpoint expects a file")?.clone())
+                }
+                "--restart" => restart = true,
+                "--lenient" => mode = Mode::Lenient,
+                _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+                _ => return Err(format!("Unknown import option: {}", arg)),
+            }
+        }
+
+        let path: String = path.ok_or("Usage: import-google-books <path|-> [--compress none|gzip|zstd] [--from-year <year>] [--to-year <year>] [--per-year] [--strategy add|overwrite|max] [--checkpoint <file>] [--restart] [--lenient]")?;
+        if path != "-" && checkpoint.is_none() {
+            checkpoint = Some(format!("{}.checkpoint", path));
+        }
+        Ok(GoogleBooksOptions {
+            compression: compression.unwrap_or_else(|| Compression::detect(&path)),
+            path,
+            from_year,
+            to_year,
+            per_year,
+            strategy,
+            checkpoint,
+            restart,
+            mode,
+        })
+    }
+}
+
+impl Group {
+    fn new(words: Vec<&str>, line_number: usize) -> Group {
+        Group {
+            three_gram: specs::ThreeGram::new(
+                words[0].to_string(),
+                words[1].to_string(),
+                words[2].to_string(),
+                0,
+            ),
+            years: Vec::new(),
+            line_number,
+            total: 0,
+        }
+    }
+
+    fn add(&mut self, year: i32, match_count: i64) {
+        self.years.push((year, clamp(match_count)));
+        self.total += match_count;
+    }
+}
+
+impl GoogleBooksReader {
+    pub fn new(input: Box<dyn BufRead + Send>, options: &GoogleBooksOptions) -> GoogleBooksReader {
+        GoogleBooksReader {
+            lines: input.lines(),
+            line_number: 0,
+            from_year: options.from_year,
+            to_year: options.to_year,
+            mode: options.mode,
+            errors: Vec::new(),
+            current: None,
+            done: false,
+        }
+    }
+
+    /// Errors of the lines skipped so far in lenient mode.
+    pub fn errors(&self) -> &[LineError] {
+        &self.errors
+    }
+
+    fn fail(&mut self, line_number: usize, message: String) -> Option<Result<Group, LineError>> {
+        let error = LineError {
+            line_number,
+            message,
+        };
+        if self.mode == Mode::Strict {
+            self.done = true;
+            self.current = None;
+            return Some(Err(error));
+        }
+        self.errors.push(error);
+        None
+    }
+
+    fn in_range(&self, year: i32) -> bool {
+        self.from_year.is_none_or(|from_year| year >= from_year)
+            && self.to_year.is_none_or(|to_year| year <= to_year)
+    }
+
+    /// Normalizes the words of a complete group and sets its total.
+    fn finish(&mut self, mut group: Group) -> Option<Result<Group, LineError>> {
+        match normalize::active().normalize_input(&group.three_gram.input()) {
+            Ok(input) => {
+                group.three_gram = specs::ThreeGram::new(
+                    input.word_1,
+                    input.word_2,
+                    input.word_3,
+                    clamp(group.total),
+                );
+                Some(Ok(group))
+            }
+            Err(message) => self.fail(group.line_number, message),
+        }
+    }
+}
+
+impl Iterator for GoogleBooksReader {
+    type Item = Result<Group, LineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.done {
+                let (_, group) = self.current.take()?;
+                if let Some(group) = self.finish(group) {
+                    return Some(group);
+                }
+                continue;
+            }
+
+            let line = match self.lines.next() {
+                Some(line) => line,
+                None => {
+                    self.done = true;
+                    continue;
+                }
+            };
+            self.line_number += 1;
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    // Only invalid UTF-8 leaves the underlying reader usable.
+                    self.done = err.kind() != io::ErrorKind::InvalidData;
+                    let message = err.to_string();
+                    if let Some(error) = self.fail(self.line_number, message) {
+                        return Some(error);
+                    }
+                    continue;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (ngram, year, match_count) = match parse_row(&line) {
+                Ok(row) => row,
+                Err(message) => {
+                    if let Some(error) = self.fail(self.line_number, message) {
+                        return Some(error);
+                    }
+                    continue;
+                }
+            };
+            if !self.in_range(year) {
+                continue;
+            }
+
+            if let Some((current_ngram, group)) = &mut self.current {
+                if current_ngram.as_str() == ngram {
+                    group.add(year, match_count);
+                    continue;
+                }
+            }
+            let words: Vec<&str> = ngram.split_whitespace().collect();
+            if words.iter().any(|word| is_tagged(word)) {
+                continue;
+            }
+            if words.len() != 3 {
+                let message = format!("expected a 3-gram, found {} words", words.len());
+                if let Some(error) = self.fail(self.line_number, message) {
+                    return Some(error);
+                }
+                continue;
+            }
+            let mut group = Group::new(words, self.line_number);
+            group.add(year, match_count);
+            if let Some((_, previous)) = self.current.replace((ngram.to_string(), group)) {
+                if let Some(previous) = self.finish(previous) {
+                    return Some(previous);
+                }
+            }
+        }
+    }
+}
+
+/// Adds up the count of every (3-gram, year) of the chunk, since
+/// normalization can fold several n-grams into the same 3-gram, and combines
+/// the sums with the stored counts like the totals. Returns the counts to set,
+/// leaving out those that don't change.
+async fn plan_years(
+    session: &Session,
+    chunk: &[Group],
+    strategy: Strategy,
+) -> Result<Vec<(specs::ThreeGram, i32)>, Box<dyn Error>> {
+    let mut sums: HashMap<(String, String, String, i32), i64> = HashMap::new();
+    for group in chunk {
+        let three_gram = &group.three_gram;
+        for (year, freq) in &group.years {
+            let key = (
+                three_gram.word_1.clone(),
+                three_gram.word_2.clone(),
+                three_gram.word_3.clone(),
+                *year,
+            );
+            *sums.entry(key).or_insert(0) += *freq as i64;
+        }
+    }
+    let sums: Vec<_> = sums.into_iter().collect();
+
+    let stored =
+        futures::future::try_join_all(sums.iter().map(|((word_1, word_2, word_3, year), _)| {
+            let input = specs::ThreeGramInput::new(word_1.clone(), word_2.clone(), word_3.clone());
+            async move {
+                trends::get_bucket(
+                    session,
+                    &input,
+                    Granularity::Year,
+                    &trends::year_bucket(*year),
+                )
+                .await
+            }
+        }))
+        .await?;
+
+    let mut years = Vec::new();
+    for (((word_1, word_2, word_3, year), sum), stored) in sums.into_iter().zip(stored) {
+        let freq = strategy.combine(stored, clamp(sum));
+        if freq != stored {
+            years.push((specs::ThreeGram::new(word_1, word_2, word_3, freq), year));
+        }
+    }
+    Ok(years)
+}
+
+/// Sets the count of every year of a planned chunk in the year buckets of
+/// the 3-grams.
+pub async fn write_years(
+    session: &Session,
+    years: &[(specs::ThreeGram, i32)],
+) -> Result<(), Box<dyn Error>> {
+    futures::future::try_join_all(years.iter().map(|(three_gram, year)| async move {
+        trends::set_bucket(
+            session,
+            &three_gram.input(),
+            Granularity::Year,
+            &trends::year_bucket(*year),
+            three_gram.freq,
+        )
+        .await
+    }))
+    .await?;
+    Ok(())
+}
+
+/// Imports a Google Books n-gram file: the totals of the year range go into
+/// the three 3-gram tables and, with `--per-year`, the count of every year
+/// into the year buckets, combined with the stored counts by the same
+/// strategy. The rows carry their own years, so the configured buckets don't
+/// count them under the document date. Returns the number of 3-grams imported.
+pub async fn import(
+    session: &Session,
+    options: &GoogleBooksOptions,
+) -> Result<usize, Box<dyn Error>> {
+    if options.per_year {
+        trends::create(session).await?;
+    }
+    let checkpoint = options.checkpoint.clone().map(Checkpoint::new);
+    if options.restart {
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.remove()?;
+        }
+    }
+
+    let input = import::open(&options.path, options.compression)?;
+    let mut groups = GoogleBooksReader::new(input, options);
+    let mut offset = import::resume(session, checkpoint.as_ref()).await?;
+    if offset > 0 {
+        println!("Resuming after 3-gram {}", offset);
+        for group in groups.by_ref().take(offset) {
+            group?;
+        }
+    }
+
+    let start_time = Instant::now();
+    let mut imported = 0;
+    loop {
+        let chunk = groups
+            .by_ref()
+            .take(import::CHUNK_SIZE)
+            .collect::<Result<Vec<_>, _>>()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let length = chunk.len();
+        let years = if options.per_year {
+            plan_years(session, &chunk, options.strategy).await?
+        } else {
+            Vec::new()
+        };
+        let three_grams = chunk.into_iter().map(|group| group.three_gram).collect();
+        let pending = Pending {
+            offset: offset + length,
+            three_grams: import::plan_chunk(session, three_grams, options.strategy).await?,
+            years,
+            dated: false,
+        };
+        import::commit_chunk(session, &pending, checkpoint.as_ref()).await?;
+
+        offset += length;
+        imported += length;
+        import::print_progress(offset, imported, start_time)?;
+    }
+    println!();
+
+    if let Some(checkpoint) = &checkpoint {
+        checkpoint.remove()?;
+    }
+    reader::print_skipped(groups.errors());
+    println!("Imported {} three-grams from {}", offset, options.path);
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn reader(input: &str, args: &[&str]) -> GoogleBooksReader {
+        let args: Vec<String> = ["-"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect();
+        let options = GoogleBooksOptions::from(&args).unwrap();
+        GoogleBooksReader::new(Box::new(Cursor::new(input.to_string())), &options)
+    }
+
+    /// The words, total and per-year counts of a group.
+    type Counts = (String, i32, Vec<(i32, i32)>);
+
+    fn groups(reader: GoogleBooksReader) -> Vec<Counts> {
+        reader
+            .map(|group| {
+                let group = group.unwrap();
+                let three_gram = group.three_gram;
+                let words = [three_gram.word_1, three_gram.word_2, three_gram.word_3].join(" ");
+                (words, three_gram.freq, group.years)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rows_parse_into_ngram_year_and_match_count() {
+        assert_eq!(parse_row("a b c\t1999\t12\t3"), Ok(("a b c", 1999, 12)));
+        assert!(parse_row("a b c\t1999\t12").is_err());
+        assert!(parse_row("a b c\tyear\t12\t3").is_err());
+        assert!(parse_row("a b c\t1999\t-1\t3").is_err());
+    }
+
+    #[test]
+    fn consecutive_years_are_grouped_and_summed() {
+        let input = "a b c\t2000\t1\t1\na b c\t2001\t2\t1\n\nd e f\t2000\t4\t2\n";
+        assert_eq!(
+            groups(reader(input, &[])),
+            vec![
+                (String::from("a b c"), 3, vec![(2000, 1), (2001, 2)]),
+                (String::from("d e f"), 4, vec![(2000, 4)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn years_outside_the_range_are_left_out() {
+        let input = "a b c\t1999\t1\t1\na b c\t2000\t2\t1\na b c\t2001\t4\t1\n\
+                     d e f\t2002\t8\t1\n";
+        assert_eq!(
+            groups(reader(input, &["--from-year", "2000", "--to-year", "2001"])),
+            vec![(String::from("a b c"), 6, vec![(2000, 2), (2001, 4)])]
+        );
+    }
+
+    #[test]
+    fn tagged_ngrams_are_skipped() {
+        assert!(is_tagged("cat_NOUN"));
+        assert!(is_tagged("._."));
+        assert!(is_tagged("_VERB_"));
+        assert!(is_tagged("_START_"));
+        assert!(!is_tagged("snake_case"));
+        assert!(!is_tagged("_"));
+
+        let input = "the_DET cat sat\t2000\t5\t1\n_DET_ _NOUN_ _VERB_\t2000\t9\t1\n\
+                     the cat sat\t2000\t5\t1\n";
+        assert_eq!(
+            groups(reader(input, &[])),
+            vec![(String::from("the cat sat"), 5, vec![(2000, 5)])]
+        );
+    }
+
+    #[test]
+    fn strict_mode_stops_at_malformed_rows_and_non_3_grams() {
+        for input in ["a b c\t2000\tmany\t1\n", "a b\t2000\t1\t1\n"] {
+            let results: Vec<_> = reader(input, &[]).collect();
+            assert_eq!(results.len(), 1);
+            assert_eq!(results[0].as_ref().err().unwrap().line_number, 1);
+        }
+    }
+
+    #[test]
+    fn lenient_mode_skips_malformed_rows_and_non_3_grams() {
+        let input = "a b c\t2000\t1\t1\na b c d\t2000\t1\t1\nbroken\na b c\t2001\t2\t1\n";
+        let mut reader = reader(input, &["--lenient"]);
+        let groups: Vec<Group> = reader.by_ref().map(Result::unwrap).collect();
+        // The skipped rows don't split the group around them.
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].years, vec![(2000, 1), (2001, 2)]);
+        let lines: Vec<usize> = reader
+            .errors()
+            .iter()
+            .map(|error| error.line_number)
+            .collect();
+        assert_eq!(lines, vec![2, 3]);
+        assert_eq!(
+            reader.errors()[0].message,
+            "expected a 3-gram, found 4 words"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::normalize;
+use crate::query_3_grams::{self, specs};
+use chrono::SecondsFormat;
+use scylla::Session;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status, Streaming};
+use uuid::Uuid;
+
+pub mod proto {
+    tonic::include_proto!("three_grams");
+}
+
+use proto::three_gram_service_server::{ThreeGramService, ThreeGramServiceServer};
+
+static DEFAULT_NUMBER_OF_PREDICTIONS: usize = 10;
+
+pub struct GrpcService {
+    session: Arc<Session>,
+}
+
+fn internal(err: Box<dyn Error>) -> Status {
+    Status::internal(err.to_string())
+}
+
+// The generated service trait fixes `Status` as the error of every handler, so
+// boxing it here would only mean unboxing it again at each `?`.
+#[allow(clippy::result_large_err)]
+fn to_input(three_gram: proto::ThreeGram) -> Result<specs::ThreeGramInput, Status> {
+    if three_gram.word_1.is_empty() || three_gram.word_2.is_empty() || three_gram.word_3.is_empty()
+    {
+        return Err(Status::invalid_argument("Input must contain 3 words"));
+    }
+    Ok(specs::ThreeGramInput::new(
+        three_gram.word_1,
+        three_gram.word_2,
+        three_gram.word_3,
+    ))
+}
+
+fn to_proto_three_gram(input: specs::ThreeGramInput) -> proto::ThreeGram {
+    proto::ThreeGram {
+        word_1: input.word_1,
+        word_2: input.word_2,
+        word_3: input.word_3,
+    }
+}
+
+fn to_pair_result(result: Option<specs::QueryResult>) -> Option<proto::PairResult> {
+    result.map(|result| proto::PairResult {
+        word_1: result.word_pair.word_1,
+        word_2: result.word_pair.word_2,
+        word_pair_map: result.word_pair_map.into_iter().collect(),
+        time_taken: result.time_taken.as_secs_f64(),
+    })
+}
+
+fn to_get_response(result: specs::ThreeGramGetResult) -> proto::GetResponse {
+    proto::GetResponse {
+        three_gram_input: Some(to_proto_three_gram(result.three_gram_input)),
+        time_taken_all: result.time_taken_all.as_secs_f64(),
+        time_taken_one: result.time_taken_one.as_secs_f64(),
+        exact_freq: result.exact_freq,
+        result_1_2_pk: to_pair_result(result.result_1_2_pk),
+        result_1_3_pk: to_pair_result(result.result_1_3_pk),
+        result_2_3_pk: to_pair_result(result.result_2_3_pk),
+        timestamp: result
+            .timestamp
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+        run_id: result.run_id.unwrap_or_default(),
+    }
+}
+
+fn to_insert_response(result: specs::ThreeGramInsertResult) -> proto::InsertResponse {
+    proto::InsertResponse {
+        three_gram_input: Some(to_proto_three_gram(result.three_gram_input)),
+        time_taken: result.time_taken.as_secs_f64(),
+        freq: result.freq,
+        timestamp: result
+            .timestamp
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+    }
+}
+
+#[tonic::async_trait]
+impl ThreeGramService for GrpcService {
+    async fn get(
+        &self,
+        request: Request<proto::ThreeGram>,
+    ) -> Result<Response<proto::GetResponse>, Status> {
+        let input = to_input(request.into_inner())?;
+        let result = query_3_grams::get_3_gram(&self.session, &input, None)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(to_get_response(result)))
+    }
+
+    async fn insert(
+        &self,
+        request: Request<proto::ThreeGram>,
+    ) -> Result<Response<proto::InsertResponse>, Status> {
+        let input = to_input(request.into_inner())?;
+        let result = query_3_grams::insert(&self.session, &input)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(to_insert_response(result)))
+    }
+
+    async fn predict_next(
+        &self,
+        request: Request<proto::PredictNextRequest>,
+    ) -> Result<Response<proto::PredictNextResponse>, Status> {
+        let request = request.into_inner();
+        let limit = match request.limit {
+            0 => DEFAULT_NUMBER_OF_PREDICTIONS,
+            limit => limit as usize,
+        };
+        let word_pair = normalize::active()
+            .normalize_pair(&specs::WordPair::new(request.word_1, request.word_2));
+        let predictions = query_3_grams::predict_next(&self.session, word_pair, limit)
+            .await
+            .map_err(internal)?
+            .into_iter()
+            .map(|prediction| proto::Prediction {
+                word: prediction.word,
+                freq: prediction.freq,
+                probability: prediction.probability,
+            })
+            .collect();
+        Ok(Response::new(proto::PredictNextResponse { predictions }))
+    }
+
+    type BulkGetStream = Pin<Box<dyn Stream<Item = Result<proto::GetResponse, Status>> + Send>>;
+
+    async fn bulk_get(
+        &self,
+        request: Request<proto::BulkGetRequest>,
+    ) -> Result<Response<Self::BulkGetStream>, Status> {
+        let inputs = request
+            .into_inner()
+            .three_grams
+            .into_iter()
+            .map(to_input)
+            .collect::<Result<Vec<_>, Status>>()?;
+        let session = self.session.clone();
+        let (sender, receiver) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            let run_id = Uuid::new_v4().to_string();
+            for input in inputs {
+                let result = query_3_grams::get_3_gram(&session, &input, Some(&run_id))
+                    .await
+                    .map(to_get_response)
+                    .map_err(internal);
+                if sender.send(result).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(receiver))))
+    }
+
+    async fn bulk_insert(
+        &self,
+        request: Request<Streaming<proto::ThreeGram>>,
+    ) -> Result<Response<proto::BulkInsertResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut inserted = 0;
+        while let Some(three_gram) = stream.message().await? {
+            let input = to_input(three_gram)?;
+            query_3_grams::insert(&self.session, &input)
+                .await
+                .map_err(internal)?;
+            inserted += 1;
+        }
+        Ok(Response::new(proto::BulkInsertResponse { inserted }))
+    }
+}
+
+/// Serves the gRPC API on `addr`, sharing `session` between requests.
+pub async fn serve(session: Session, addr: SocketAddr) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let service = GrpcService {
+        session: Arc::new(session),
+    };
+    println!("Serving the 3-gram gRPC API on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(ThreeGramServiceServer::new(service))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
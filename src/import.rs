@@ -0,0 +1,591 @@
+use crate::export::{Compression, Format, BINARY_MAGIC};
+use crate::google_books;
+use crate::leaderboard;
+use crate::marginals;
+use crate::normalize;
+use crate::query_3_grams::{self, specs};
+use crate::reader;
+use crate::trends;
+use flate2::read::MultiGzDecoder;
+use scylla::Session;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::time::Instant;
+
+pub static CHUNK_SIZE: usize = 1000;
+
+pub type Records<'a> = Box<dyn Iterator<Item = Result<specs::ThreeGram, Box<dyn Error>>> + 'a>;
+
+/// How an imported frequency is combined with the one already stored.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Strategy {
+    Overwrite,
+    Add,
+    Max,
+}
+
+pub struct ImportOptions {
+    pub path: String,
+    pub format: Format,
+    pub compression: Compression,
+    pub strategy: Strategy,
+    pub checkpoint: Option<String>,
+    pub restart: bool,
+}
+
+/// Remembers how many records of the input have been committed, so that an
+/// interrupted import continues after them.
+pub struct Checkpoint {
+    path: String,
+}
+
+/// The frequencies a chunk sets, saved before the chunk is written. They are
+/// absolute rather than increments, so a chunk interrupted halfway can be
+/// written again on resume without counting anything twice.
+#[derive(Serialize, Deserialize)]
+pub struct Pending {
+    pub offset: usize,
+    pub three_grams: Vec<specs::ThreeGram>,
+    /// Per-year counts of a Google Books chunk, the 3-gram carrying the count.
+    #[serde(default)]
+    pub years: Vec<(specs::ThreeGram, i32)>,
+    /// Whether the configured time buckets count the increases under the
+    /// document date, like inserts do.
+    #[serde(default)]
+    pub dated: bool,
+}
+
+struct BinaryRecords {
+    input: Box<dyn BufRead>,
+    done: bool,
+}
+
+impl Strategy {
+    pub fn parse(name: &str) -> Result<Strategy, String> {
+        match name {
+            "overwrite" => Ok(Strategy::Overwrite),
+            "add" => Ok(Strategy::Add),
+            "max" => Ok(Strategy::Max),
+            _ => Err(format!("Unknown import strategy: {}", name)),
+        }
+    }
+
+    pub fn combine(&self, stored: i32, imported: i32) -> i32 {
+        match self {
+            Strategy::Overwrite => imported,
+            Strategy::Add => stored.saturating_add(imported),
+            Strategy::Max => stored.max(imported),
+        }
+    }
+}
+
+impl ImportOptions {
+    /// Parses `<path|-> [--format csv|jsonl|binary] [--compress none|gzip|zstd]
+    /// [--strategy overwrite|add|max] [--checkpoint <file>] [--restart]`. The
+    /// checkpoint defaults to `<path>.checkpoint`; stdin is never checkpointed.
+    pub fn from(args: &[String]) -> Result<ImportOptions, String> {
+        let mut path = None;
+        let mut format = None;
+        let mut compression = None;
+        let mut strategy = Strategy::Overwrite;
+        let mut checkpoint = None;
+        let mut restart = false;
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--format" => {
+                    format = Some(Format::parse(
+                        iter.next().ok_or("--format expects a format")?,
+                    )?)
+                }
+                "--compress" => {
+                    compression = Some(Compression::parse(
+                        iter.next().ok_or("--compress expects a compression")?,
+                    )?)
+                }
+                "--strategy" => {
+                    strategy = Strategy::parse(iter.next().ok_or("--strategy expects a strategy")?)?
+                }
+                "--checkpoint" => {
+                    checkpoint = Some(iter.next().ok_or("--checkpoint expects a file")?.clone())
+                }
+                "--restart" => restart = true,
+                _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+                _ => return Err(format!("Unknown import option: {}", arg)),
+            }
+        }
+
+        let path: String = path.ok_or("Usage: import <path|-> [--format csv|jsonl|binary] [--compress none|gzip|zstd] [--strategy overwrite|add|max] [--checkpoint <file>] [--restart]")?;
+        if path != "-" && checkpoint.is_none() {
+            checkpoint = Some(format!("{}.checkpoint", path));
+        }
+        Ok(ImportOptions {
+            format: format.unwrap_or_else(|| Format::detect(&path)),
+            compression: compression.unwrap_or_else(|| Compression::detect(&path)),
+            path,
+            strategy,
+            checkpoint,
+            restart,
+        })
+    }
+}
+
+impl Checkpoint {
+    pub fn new(path: String) -> Checkpoint {
+        Checkpoint { path }
+    }
+
+    /// Number of records committed by an earlier run, 0 without a checkpoint.
+    pub fn load(&self) -> Result<usize, Box<dyn Error>> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => Ok(content
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid checkpoint file {}", self.path))?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Replaces the checkpoint atomically, so a crash never leaves it half
+    /// written.
+    pub fn save(&self, offset: usize) -> io::Result<()> {
+        let tmp_path = format!("{}.tmp", self.path);
+        fs::write(&tmp_path, offset.to_string())?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    /// Removes the checkpoint and any chunk left pending.
+    pub fn remove(&self) -> io::Result<()> {
+        remove_file(&self.path)?;
+        remove_file(&self.pending_path())
+    }
+
+    fn pending_path(&self) -> String {
+        format!("{}.pending", self.path)
+    }
+
+    /// The chunk an earlier run was writing when it was interrupted.
+    pub fn load_pending(&self) -> Result<Option<Pending>, Box<dyn Error>> {
+        match fs::read_to_string(self.pending_path()) {
+            Ok(content) => Ok(Some(serde_json::from_str(&content).map_err(|_| {
+                format!("Invalid pending chunk file {}", self.pending_path())
+            })?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save_pending(&self, pending: &Pending) -> Result<(), Box<dyn Error>> {
+        let tmp_path = format!("{}.tmp", self.pending_path());
+        fs::write(&tmp_path, serde_json::to_string(pending)?)?;
+        fs::rename(&tmp_path, self.pending_path())?;
+        Ok(())
+    }
+
+    /// Moves the checkpoint past a chunk that was written completely.
+    fn commit(&self, offset: usize) -> io::Result<()> {
+        self.save(offset)?;
+        remove_file(&self.pending_path())
+    }
+}
+
+fn remove_file(path: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+impl Pending {
+    /// Sets every frequency of the chunk. Those already set are skipped, so
+    /// writing the same chunk twice changes nothing.
+    pub async fn write(&self, session: &Session) -> Result<(), Box<dyn Error>> {
+        futures::future::try_join_all(
+            self.three_grams
+                .iter()
+                .map(|three_gram| apply(session, three_gram, self.dated)),
+        )
+        .await?;
+        google_books::write_years(session, &self.years).await
+    }
+}
+
+/// Opens `path`, `-` meaning stdin, and decompresses it.
+pub fn open(
+    path: &str,
+    compression: Compression,
+) -> Result<Box<dyn BufRead + Send>, Box<dyn Error>> {
+    let input: Box<dyn Read + Send> = if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path).map_err(|err| format!("Could not open {}: {}", path, err))?)
+    };
+    Ok(match compression {
+        Compression::None => Box::new(BufReader::new(input)),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(input))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(input)?)),
+    })
+}
+
+fn read_word(input: &mut dyn BufRead) -> io::Result<String> {
+    let mut length = [0; 2];
+    input.read_exact(&mut length)?;
+    let mut word = vec![0; u16::from_le_bytes(length) as usize];
+    input.read_exact(&mut word)?;
+    String::from_utf8(word).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+impl BinaryRecords {
+    fn new(mut input: Box<dyn BufRead>) -> Result<BinaryRecords, Box<dyn Error>> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != BINARY_MAGIC {
+            return Err("Not a binary 3-gram snapshot".into());
+        }
+        Ok(BinaryRecords { input, done: false })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<specs::ThreeGram>> {
+        if self.input.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let word_1 = read_word(&mut self.input)?;
+        let word_2 = read_word(&mut self.input)?;
+        let word_3 = read_word(&mut self.input)?;
+        let mut freq = [0; 4];
+        self.input.read_exact(&mut freq)?;
+        Ok(Some(specs::ThreeGram::new(
+            word_1,
+            word_2,
+            word_3,
+            i32::from_le_bytes(freq),
+        )))
+    }
+}
+
+impl Iterator for BinaryRecords {
+    type Item = Result<specs::ThreeGram, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_record() {
+            Ok(Some(three_gram)) => Some(Ok(three_gram)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err.into()))
+            }
+        }
+    }
+}
+
+fn normalize_record(three_gram: specs::ThreeGram) -> Result<specs::ThreeGram, Box<dyn Error>> {
+    let input = normalize::active().normalize_input(&three_gram.input())?;
+    Ok(specs::ThreeGram::new(
+        input.word_1,
+        input.word_2,
+        input.word_3,
+        three_gram.freq,
+    ))
+}
+
+/// Reads the records of a snapshot written by `export`, with the words
+/// normalized by the active pipeline.
+pub fn records(
+    input: Box<dyn BufRead + Send>,
+    format: Format,
+) -> Result<Records<'static>, Box<dyn Error>> {
+    let records: Records<'static> = match format {
+        Format::Binary => Box::new(BinaryRecords::new(input)?),
+        Format::Csv | Format::Jsonl => {
+            let reader_format = if format == Format::Csv {
+                reader::Format::Csv
+            } else {
+                reader::Format::Jsonl
+            };
+            // Snapshots have no comment lines, and a word may start with `#`.
+            let records =
+                reader::ThreeGramReader::new(input, reader_format, reader::Mode::Strict, false)?;
+            Box::new(records.map(|record| record.map_err(Into::into)))
+        }
+    };
+    Ok(Box::new(
+        records.map(|record| record.and_then(normalize_record)),
+    ))
+}
+
+/// Merges repeated 3-grams of a chunk, so that concurrent writes never race
+/// on the same row.
+fn merge(chunk: Vec<specs::ThreeGram>, strategy: Strategy) -> Vec<specs::ThreeGram> {
+    let mut merged: Vec<specs::ThreeGram> = Vec::new();
+    let mut positions = HashMap::new();
+    for three_gram in chunk {
+        let key = (
+            three_gram.word_1.clone(),
+            three_gram.word_2.clone(),
+            three_gram.word_3.clone(),
+        );
+        match positions.get(&key) {
+            Some(&index) => {
+                let existing: &mut specs::ThreeGram = &mut merged[index];
+                existing.freq = strategy.combine(existing.freq, three_gram.freq);
+            }
+            None => {
+                positions.insert(key, merged.len());
+                merged.push(three_gram);
+            }
+        }
+    }
+    merged
+}
+
+/// Sets the frequency of the 3-gram and moves the marginals by the difference
+/// to the stored one, so they stay in sync whatever the strategy. When `dated`
+/// an increase is also counted in the configured time buckets; a decrease
+/// can't be attributed to a period and leaves them alone.
+async fn apply(
+    session: &Session,
+    three_gram: &specs::ThreeGram,
+    dated: bool,
+) -> Result<(), Box<dyn Error>> {
+    let input = three_gram.input();
+    let stored = query_3_grams::get_freq(session, &input).await?;
+    let freq = three_gram.freq;
+    if freq == stored {
+        return Ok(());
+    }
+    query_3_grams::write_freq(session, &input, freq).await?;
+    marginals::record(session, &input, freq as i64 - stored as i64).await?;
+    leaderboard::record(session, &input, stored, freq).await?;
+    if dated && freq > stored && trends::enabled() {
+        trends::record(session, &input, freq - stored).await?;
+    }
+    Ok(())
+}
+
+/// Combines a chunk of 3-grams with the stored frequencies and returns the
+/// frequencies to set, leaving out those that don't change.
+pub async fn plan_chunk(
+    session: &Session,
+    chunk: Vec<specs::ThreeGram>,
+    strategy: Strategy,
+) -> Result<Vec<specs::ThreeGram>, Box<dyn Error>> {
+    let chunk = merge(chunk, strategy);
+    let inputs: Vec<specs::ThreeGramInput> = chunk.iter().map(specs::ThreeGram::input).collect();
+    let stored = futures::future::try_join_all(
+        inputs
+            .iter()
+            .map(|input| query_3_grams::get_freq(session, input)),
+    )
+    .await?;
+    Ok(chunk
+        .into_iter()
+        .zip(stored)
+        .filter_map(|(mut three_gram, stored)| {
+            three_gram.freq = strategy.combine(stored, three_gram.freq);
+            (three_gram.freq != stored).then_some(three_gram)
+        })
+        .collect())
+}
+
+/// Writes a planned chunk to all three tables concurrently. With a checkpoint
+/// the chunk is saved as pending first and the checkpoint moved past it once
+/// it is written.
+pub async fn commit_chunk(
+    session: &Session,
+    pending: &Pending,
+    checkpoint: Option<&Checkpoint>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.save_pending(pending)?;
+    }
+    pending.write(session).await?;
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.commit(pending.offset)?;
+    }
+    Ok(())
+}
+
+/// Returns the number of records committed by an earlier run, first finishing
+/// the chunk it was writing when it was interrupted.
+pub async fn resume(
+    session: &Session,
+    checkpoint: Option<&Checkpoint>,
+) -> Result<usize, Box<dyn Error>> {
+    let checkpoint = match checkpoint {
+        Some(checkpoint) => checkpoint,
+        None => return Ok(0),
+    };
+    if let Some(pending) = checkpoint.load_pending()? {
+        println!(
+            "Finishing the chunk interrupted before record {}",
+            pending.offset
+        );
+        pending.write(session).await?;
+        checkpoint.commit(pending.offset)?;
+    }
+    checkpoint.load()
+}
+
+/// Overwrites the progress line with the number of records committed so far
+/// and the rate of this run.
+pub fn print_progress(offset: usize, imported: usize, start_time: Instant) -> io::Result<()> {
+    print!(
+        "\rImported {} three-grams ({:.0} per second)",
+        offset,
+        imported as f64 / start_time.elapsed().as_secs_f64()
+    );
+    io::stdout().flush()
+}
+
+/// Writes `records` to all three tables in chunks, saving the number of
+/// committed records to `checkpoint` after each chunk and skipping the records
+/// an earlier run already committed. Returns the number of records read.
+pub async fn import_records(
+    session: &Session,
+    mut records: Records<'_>,
+    strategy: Strategy,
+    checkpoint: Option<&Checkpoint>,
+) -> Result<usize, Box<dyn Error>> {
+    let mut offset = resume(session, checkpoint).await?;
+    if offset > 0 {
+        println!("Resuming after record {}", offset);
+        for record in records.by_ref().take(offset) {
+            record?;
+        }
+    }
+
+    let start_time = Instant::now();
+    let mut imported = 0;
+    loop {
+        let chunk = records
+            .by_ref()
+            .take(CHUNK_SIZE)
+            .collect::<Result<Vec<_>, _>>()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let length = chunk.len();
+        let pending = Pending {
+            offset: offset + length,
+            three_grams: plan_chunk(session, chunk, strategy).await?,
+            years: Vec::new(),
+            dated: true,
+        };
+        commit_chunk(session, &pending, checkpoint).await?;
+
+        offset += length;
+        imported += length;
+        print_progress(offset, imported, start_time)?;
+    }
+    println!();
+
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.remove()?;
+    }
+    Ok(offset)
+}
+
+/// Loads a snapshot written by `export` into all three tables.
+pub async fn import(session: &Session, options: &ImportOptions) -> Result<usize, Box<dyn Error>> {
+    let checkpoint = options.checkpoint.clone().map(Checkpoint::new);
+    if options.restart {
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.remove()?;
+        }
+    }
+    let input = open(&options.path, options.compression)?;
+    let records = records(input, options.format)?;
+    let count = import_records(session, records, options.strategy, checkpoint.as_ref()).await?;
+    println!("Imported {} three-grams from {}", count, options.path);
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export;
+
+    fn three_gram(word_1: &str, word_2: &str, word_3: &str, freq: i32) -> specs::ThreeGram {
+        specs::ThreeGram::new(word_1.into(), word_2.into(), word_3.into(), freq)
+    }
+
+    fn tuples(three_grams: &[specs::ThreeGram]) -> Vec<(&str, &str, &str, i32)> {
+        three_grams
+            .iter()
+            .map(|three_gram| {
+                (
+                    three_gram.word_1.as_str(),
+                    three_gram.word_2.as_str(),
+                    three_gram.word_3.as_str(),
+                    three_gram.freq,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn snapshots_survive_an_export_import_round_trip() {
+        let three_grams = vec![
+            three_gram("####", "was", "great", 5),
+            three_gram("#", "a,b", "\"c\"", 1),
+            three_gram("ü", "é", "ß", 70000),
+        ];
+        for format in [Format::Csv, Format::Jsonl, Format::Binary] {
+            for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+                let path =
+                    std::env::temp_dir().join(format!("import-test-{}", uuid::Uuid::new_v4()));
+                let path = path.to_str().unwrap();
+                export::write_snapshot(path, format, compression, &three_grams).unwrap();
+                let imported = records(open(path, compression).unwrap(), format)
+                    .unwrap()
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
+                fs::remove_file(path).unwrap();
+                assert_eq!(tuples(&imported), tuples(&three_grams));
+            }
+        }
+    }
+
+    #[test]
+    fn strategies_combine_stored_and_imported_frequencies() {
+        assert_eq!(Strategy::Overwrite.combine(5, 3), 3);
+        assert_eq!(Strategy::Add.combine(5, 3), 8);
+        assert_eq!(Strategy::Add.combine(i32::MAX, 3), i32::MAX);
+        assert_eq!(Strategy::Max.combine(5, 3), 5);
+        assert_eq!(Strategy::Max.combine(3, 5), 5);
+        assert!(Strategy::parse("min").is_err());
+    }
+
+    #[test]
+    fn merge_combines_repeated_3_grams_by_strategy() {
+        let chunk = || {
+            vec![
+                three_gram("a", "b", "c", 2),
+                three_gram("d", "e", "f", 1),
+                three_gram("a", "b", "c", 5),
+                three_gram("a", "b", "c", 3),
+            ]
+        };
+        for (strategy, freq) in [
+            (Strategy::Overwrite, 3),
+            (Strategy::Add, 10),
+            (Strategy::Max, 5),
+        ] {
+            assert_eq!(
+                tuples(&merge(chunk(), strategy)),
+                vec![("a", "b", "c", freq), ("d", "e", "f", 1)]
+            );
+        }
+    }
+}
@@ -0,0 +1,367 @@
+use crate::corpus::{self, Corpus};
+use crate::query_3_grams::{specs, statements};
+use crate::summary;
+use futures::StreamExt;
+use scylla::Session;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+static THRESHOLD_KEY: &str = "top_threshold";
+static SIZE_KEY: &str = "top_size";
+static COUNT_KEY: &str = "top_count";
+static PAGE_SIZE: i32 = 1000;
+/// All rows live in one partition, so the table reads in frequency order.
+static BOARD: i32 = 0;
+
+/// The state of the leaderboard, `None` until it is loaded and `Some(None)`
+/// when the board was never built. Updates only hold the lock while they
+/// change it, not while they write to the database.
+static STATE: Mutex<Option<Option<Board>>> = Mutex::const_new(None);
+
+/// How many 3-grams the board keeps and holds, and the lowest frequency that
+/// gets a 3-gram onto it: 1 while the board isn't full, the frequency of its
+/// last entry once it is.
+#[derive(Clone, Copy, PartialEq)]
+struct Board {
+    threshold: i32,
+    size: usize,
+    count: usize,
+}
+
+pub struct TopFilter {
+    pub word: Option<String>,
+    pub min_length: usize,
+}
+
+impl TopFilter {
+    /// Every word must be at least `min_length` characters long and one of
+    /// them equal to `word`.
+    fn matches(&self, three_gram: &specs::ThreeGram) -> bool {
+        let words = [&three_gram.word_1, &three_gram.word_2, &three_gram.word_3];
+        words
+            .iter()
+            .all(|word| word.chars().count() >= self.min_length)
+            && self.word.as_ref().is_none_or(|word| words.contains(&word))
+    }
+}
+
+pub async fn create(session: &Session, corpus: &Corpus) -> Result<(), Box<dyn Error>> {
+    session
+        .query(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {} (board int, freq int, word_1 text, word_2 text, word_3 text, PRIMARY KEY (board, freq, word_1, word_2, word_3)) WITH CLUSTERING ORDER BY (freq DESC, word_1 ASC, word_2 ASC, word_3 ASC)",
+                corpus.table("top_three_grams")
+            ),
+            (),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn load(session: &Session) -> Result<Option<Board>, Box<dyn Error>> {
+    let mut values = Vec::new();
+    for key in [THRESHOLD_KEY, SIZE_KEY, COUNT_KEY] {
+        let value = corpus::get_metadata(session, corpus::active(), key).await?;
+        match value.and_then(|value| value.parse::<usize>().ok()) {
+            Some(value) => values.push(value),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(Board {
+        threshold: values[0] as i32,
+        size: values[1],
+        count: values[2],
+    }))
+}
+
+async fn save(session: &Session, board: &Board) -> Result<(), Box<dyn Error>> {
+    for (key, value) in [
+        (THRESHOLD_KEY, board.threshold as usize),
+        (SIZE_KEY, board.size),
+        (COUNT_KEY, board.count),
+    ] {
+        corpus::set_metadata(session, corpus::active(), key, &value.to_string()).await?;
+    }
+    Ok(())
+}
+
+/// The last entry of the board, read in reverse clustering order.
+async fn last(session: &Session) -> Result<Option<specs::ThreeGram>, Box<dyn Error>> {
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "SELECT word_1, word_2, word_3, freq FROM {} WHERE board = ? ORDER BY freq ASC, word_1 DESC, word_2 DESC, word_3 DESC LIMIT 1",
+            corpus::table("top_three_grams")
+        ),
+    )
+    .await?;
+    let row = session
+        .execute(&prepared, (BOARD,))
+        .await?
+        .maybe_first_row_typed::<(String, String, String, i32)>()?;
+    Ok(row
+        .map(|(word_1, word_2, word_3, freq)| specs::ThreeGram::new(word_1, word_2, word_3, freq)))
+}
+
+async fn contains(
+    session: &Session,
+    input: &specs::ThreeGramInput,
+    freq: i32,
+) -> Result<bool, Box<dyn Error>> {
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "SELECT freq FROM {} WHERE board = ? AND freq = ? AND word_1 = ? AND word_2 = ? AND word_3 = ?",
+            corpus::table("top_three_grams")
+        ),
+    )
+    .await?;
+    let row = session
+        .execute(
+            &prepared,
+            (
+                BOARD,
+                freq,
+                input.word_1.as_str(),
+                input.word_2.as_str(),
+                input.word_3.as_str(),
+            ),
+        )
+        .await?
+        .maybe_first_row_typed::<(i32,)>()?;
+    Ok(row.is_some())
+}
+
+async fn delete(
+    session: &Session,
+    input: &specs::ThreeGramInput,
+    freq: i32,
+) -> Result<(), Box<dyn Error>> {
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "DELETE FROM {} WHERE board = ? AND freq = ? AND word_1 = ? AND word_2 = ? AND word_3 = ?",
+            corpus::table("top_three_grams")
+        ),
+    )
+    .await?;
+    session
+        .execute(
+            &prepared,
+            (
+                BOARD,
+                freq,
+                input.word_1.as_str(),
+                input.word_2.as_str(),
+                input.word_3.as_str(),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+/// The board of this process, loaded on first use. The lock is only held
+/// for the load, never across the writes of an update.
+async fn current(session: &Session) -> Result<Option<Board>, Box<dyn Error>> {
+    let mut state = STATE.lock().await;
+    if state.is_none() {
+        *state = Some(load(session).await?);
+    }
+    Ok(state.flatten())
+}
+
+/// Counts a new entry and returns whether the board was full and has to drop
+/// its last entry for it. Claiming the place under the lock keeps concurrent
+/// inserts of this process from pushing the board past its size.
+async fn claim() -> bool {
+    let mut state = STATE.lock().await;
+    match state.as_mut() {
+        Some(Some(board)) if board.count >= board.size => true,
+        Some(Some(board)) => {
+            board.count += 1;
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Whether the board holds as many entries as it keeps.
+async fn is_full() -> bool {
+    matches!(*STATE.lock().await, Some(Some(board)) if board.count >= board.size)
+}
+
+/// Sets the threshold of a full board to the frequency of its last entry, if
+/// it was read, and returns the board to save.
+async fn settle(last_freq: Option<i32>) -> Option<Board> {
+    let mut state = STATE.lock().await;
+    let board = state.as_mut()?.as_mut()?;
+    if let Some(last_freq) = last_freq.filter(|_| board.count >= board.size) {
+        board.threshold = last_freq;
+    }
+    Some(*board)
+}
+
+/// Moves the 3-gram from `old_freq` to `new_freq` on the leaderboard, or
+/// puts it on once it reaches the threshold. A full board drops its last
+/// entry for every new one, so it never holds more than its size; an entry
+/// whose frequency drops stays until the next rebuild. Concurrent inserts
+/// only share the lock for the bookkeeping, so the saved metadata may lag an
+/// update behind until the next one.
+pub async fn record(
+    session: &Session,
+    input: &specs::ThreeGramInput,
+    old_freq: i32,
+    new_freq: i32,
+) -> Result<(), Box<dyn Error>> {
+    let board = match current(session).await? {
+        Some(board) => board,
+        None => return Ok(()),
+    };
+    // Every entry is at least at the threshold, so below it there is nothing
+    // to look up.
+    let on_board = old_freq >= board.threshold && contains(session, input, old_freq).await?;
+    if !on_board && new_freq < board.threshold {
+        return Ok(());
+    }
+    let evict = !on_board && claim().await;
+
+    let table = corpus::table("top_three_grams");
+    let cqls = [
+        format!(
+            "DELETE FROM {} WHERE board = ? AND freq = ? AND word_1 = ? AND word_2 = ? AND word_3 = ?",
+            table
+        ),
+        format!(
+            "INSERT INTO {} (board, freq, word_1, word_2, word_3) VALUES (?, ?, ?, ?, ?)",
+            table
+        ),
+    ];
+    let batch = statements::prepare_batch(session, &[cqls[0].as_str(), cqls[1].as_str()]).await?;
+    let words = (
+        input.word_1.as_str(),
+        input.word_2.as_str(),
+        input.word_3.as_str(),
+    );
+    session
+        .batch(
+            &batch,
+            (
+                (BOARD, old_freq, words.0, words.1, words.2),
+                (BOARD, new_freq, words.0, words.1, words.2),
+            ),
+        )
+        .await?;
+
+    if evict {
+        // Bound first: a temporary of the condition would live across the
+        // await and make the future unusable from the servers.
+        let lowest = last(session).await?;
+        if let Some(lowest) = lowest {
+            delete(session, &lowest.input(), lowest.freq).await?;
+        }
+    }
+    // Only a full board takes its threshold from the last entry.
+    let last_freq = if is_full().await {
+        last(session).await?.map(|last| last.freq)
+    } else {
+        None
+    };
+    match settle(last_freq).await {
+        Some(updated) if updated != board => save(session, &updated).await,
+        _ => Ok(()),
+    }
+}
+
+/// Recomputes the `size` most frequent 3-grams of the active corpus with a
+/// full scan and replaces the leaderboard with them.
+pub async fn rebuild(
+    session: Session,
+    size: usize,
+    parallelism: usize,
+) -> Result<usize, Box<dyn Error>> {
+    let session = Arc::new(session);
+    let top = summary::compute(session.clone(), size, parallelism)
+        .await?
+        .top;
+
+    let table = corpus::table("top_three_grams");
+    session.query(format!("TRUNCATE {}", table), ()).await?;
+    let prepared = statements::prepare(
+        &session,
+        &format!(
+            "INSERT INTO {} (board, freq, word_1, word_2, word_3) VALUES (?, ?, ?, ?, ?)",
+            table
+        ),
+    )
+    .await?;
+    futures::future::try_join_all(top.iter().map(|three_gram| {
+        session.execute(
+            &prepared,
+            (
+                BOARD,
+                three_gram.freq,
+                three_gram.word_1.as_str(),
+                three_gram.word_2.as_str(),
+                three_gram.word_3.as_str(),
+            ),
+        )
+    }))
+    .await?;
+
+    // A board that isn't full takes every 3-gram.
+    let threshold = match top.last() {
+        Some(three_gram) if top.len() == size => three_gram.freq,
+        _ => 1,
+    };
+    let board = Board {
+        threshold,
+        size,
+        count: top.len(),
+    };
+    let mut state = STATE.lock().await;
+    save(&session, &board).await?;
+    *state = Some(Some(board));
+    Ok(top.len())
+}
+
+/// The `limit` most frequent 3-grams on the leaderboard passing `filter`.
+pub async fn top(
+    session: &Session,
+    limit: usize,
+    filter: &TopFilter,
+) -> Result<Vec<specs::ThreeGram>, Box<dyn Error>> {
+    let board = match *STATE.lock().await {
+        Some(board) => board,
+        None => load(session).await?,
+    };
+    if board.is_none() {
+        return Err("The leaderboard was never built, run `top rebuild` first".into());
+    }
+    let mut prepared = statements::prepare(
+        session,
+        &format!(
+            "SELECT word_1, word_2, word_3, freq FROM {} WHERE board = ?",
+            corpus::table("top_three_grams")
+        ),
+    )
+    .await?;
+    prepared.set_page_size(PAGE_SIZE);
+    let mut rows = session
+        .execute_iter(prepared, (BOARD,))
+        .await?
+        .into_typed::<(String, String, String, i32)>();
+
+    let mut top = Vec::new();
+    while let Some(row) = rows.next().await {
+        if top.len() >= limit {
+            break;
+        }
+        let (word_1, word_2, word_3, freq) = row?;
+        let three_gram = specs::ThreeGram::new(word_1, word_2, word_3, freq);
+        if filter.matches(&three_gram) {
+            top.push(three_gram);
+        }
+    }
+    Ok(top)
+}
@@ -1,18 +1,53 @@
 use query_3_grams::specs;
-use scylla::{Session, SessionBuilder};
+use scylla::Session;
 use std::error::Error;
 use std::io::{self, Write};
 
+mod check;
+mod cloze;
+mod collocations;
+mod commands;
+mod compare;
+mod corpus;
+mod export;
+mod generate;
+mod google_books;
+mod grpc;
+mod import;
+mod leaderboard;
+mod marginals;
+pub mod metrics;
+mod normalize;
 pub mod query_3_grams;
+pub mod query_n_grams;
 mod reader;
+mod server;
 pub mod stats;
+mod summary;
+mod tokenizer;
+mod trends;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let uri = std::env::var("SCYLLA_URI").unwrap_or_else(|_| "127.0.0.1:9042".to_string());
+    let args = normalize::init(std::env::args().skip(1).collect())?;
+    let args = tokenizer::init(args)?;
+    let args = query_n_grams::schema::init(args)?;
+    let args = corpus::init(args)?;
+    let args = trends::init(args)?;
+    if !args.is_empty() {
+        let code = commands::run(&args).await?;
+        std::process::exit(code);
+    }
+
+    let session: Session = commands::connect().await?;
 
-    let session: Session = SessionBuilder::new().known_node(uri).build().await?;
+    run_menu(&session).await?;
+    Ok(())
+}
 
+/// Shows the interactive menu once and runs the chosen action. Returns
+/// `false` when the user chose to exit.
+pub async fn run_menu(session: &Session) -> Result<bool, Box<dyn Error>> {
     println!("Pleas choose an action:");
     println!("[1]: Get frequencies for certain three-grams");
     println!("[2]: Insert a three-gram (or increment its frequency)");
@@ -44,29 +79,36 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         1 => {
                             println!("\nYou chose to get data about a specific three-gram");
                             println!("Please enter the three-gram you want to get data about");
-                            println!("Example: \"word_1 word_2 word_3\"");
+                            println!("Example: \"word_1 word_2 word_3\" (longer texts yield several 3-grams)");
                             print!("> ");
                             io::stdout().flush().unwrap();
 
                             let mut input = String::new();
                             io::stdin().read_line(&mut input).unwrap();
-                            let three_gram = specs::ThreeGramInput::from(input);
+                            let three_grams = specs::ThreeGramInput::from_text(&input);
 
-                            match three_gram {
-                                Ok(three_gram) => {
-                                    let result =
-                                        query_3_grams::get_3_gram(&session, &three_gram).await?;
+                            match three_grams {
+                                Ok(three_grams) => {
+                                    for three_gram in three_grams {
+                                        let result =
+                                            query_3_grams::get_3_gram(session, &three_gram, None)
+                                                .await?;
 
-                                    println!("\n{:?}", result);
-                                    println!("This information can also be found in file:");
-                                    println!(
-                                        "/home/projekt/query-results/select/{}-{}-{}",
-                                        three_gram.word_1, three_gram.word_2, three_gram.word_3
-                                    );
+                                        println!("\n{:?}", result);
+                                        match query_3_grams::write_result(&result) {
+                                            Ok(path) => {
+                                                println!(
+                                                    "This information can also be found in file:"
+                                                );
+                                                println!("{}", path);
+                                            }
+                                            Err(err) => eprintln!("{}", err),
+                                        }
+                                    }
                                 }
                                 Err(e) => {
                                     eprintln!("{}", e);
-                                    return Ok(());
+                                    return Ok(true);
                                 }
                             }
                         }
@@ -75,6 +117,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                             println!("Default input file is /home/projekt/query-inputs/input");
                             println!("Do you want to use the default input file?");
                             println!("(If you choose no, the file still has to be in the same directory)");
+                            println!("(.csv, .tsv and .jsonl files are read as such, anything else as text)");
                             println!("[1]: Yes");
                             println!("[2]: No");
                             print!("> ");
@@ -90,28 +133,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                         println!("\nYou chose to use the default input file");
                                         let input_path =
                                             String::from("/home/projekt/query-inputs/input");
-                                        let vec = reader::read(input_path);
-                                        match vec {
-                                            Ok(three_grams) => {
-                                                let mut three_gram_vector: Vec<
-                                                    specs::ThreeGramInput,
-                                                > = Vec::new();
-                                                for three_gram in three_grams {
-                                                    three_gram_vector.push(three_gram);
-                                                }
-                                                query_3_grams::get_bulk(
-                                                    &session,
-                                                    &three_gram_vector,
-                                                )
-                                                .await?;
-                                                println!("Results can be found in directory:");
-                                                println!("/home/projekt/query-results/select");
-                                            }
-                                            _ => {
-                                                eprintln!("Something went wrong");
-                                                ()
-                                            }
-                                        };
+                                        if let Err(err) = run_bulk(session, &input_path).await {
+                                            eprintln!("{}", err);
+                                        }
                                     }
                                     2 => {
                                         println!("\nYou chose to use a custom input file");
@@ -124,28 +148,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                         let input_path =
                                             String::from("/home/projekt/query-inputs/")
                                                 + input.trim();
-                                        let vec = reader::read(input_path);
-                                        match vec {
-                                            Ok(three_grams) => {
-                                                let mut three_gram_vector: Vec<
-                                                    specs::ThreeGramInput,
-                                                > = Vec::new();
-                                                for three_gram in three_grams {
-                                                    three_gram_vector.push(three_gram);
-                                                }
-                                                query_3_grams::get_bulk(
-                                                    &session,
-                                                    &three_gram_vector,
-                                                )
-                                                .await?;
-                                                println!("\nResults can be found in directory:");
-                                                println!("/home/projekt/query-results/select");
-                                            }
-                                            _ => {
-                                                eprintln!("Something went wrong");
-                                                ()
-                                            }
-                                        };
+                                        if let Err(err) = run_bulk(session, &input_path).await {
+                                            eprintln!("{}", err);
+                                        }
                                     }
                                     _ => {
                                         println!("Invalid input");
@@ -164,41 +169,43 @@ async fn main() -> Result<(), Box<dyn Error>> {
             2 => {
                 println!("\nYou chose to insert a three-gram (or increment its frequency)");
                 println!("Please enter the three-gram you want to insert");
-                println!("Example: \"word_1 word_2 word_3\"");
+                println!("Example: \"word_1 word_2 word_3\" (longer texts yield several 3-grams)");
                 print!("> ");
                 io::stdout().flush().unwrap();
 
                 let mut input = String::new();
                 io::stdin().read_line(&mut input).unwrap();
-                let three_gram = specs::ThreeGramInput::from(input);
+                let three_grams = specs::ThreeGramInput::from_text(&input);
 
-                match three_gram {
-                    Ok(three_gram) => {
-                        let result = query_3_grams::insert(&session, &three_gram).await?;
-                        let file_path = String::from("/home/projekt/query-results/insert/")
-                            + &three_gram.word_1
-                            + "-"
-                            + &three_gram.word_2
-                            + "-"
-                            + &three_gram.word_3
-                            + "-"
-                            + &result.freq.to_string();
-                        let option = query_3_grams::writer::WriteOptions::FILE(file_path);
-                        let res = query_3_grams::writer::write_insert(option, &result);
-                        if let Err(err) = res {
-                            eprintln!("{}", err);
-                            return Ok(());
+                match three_grams {
+                    Ok(three_grams) => {
+                        for three_gram in three_grams {
+                            let result = query_3_grams::insert(session, &three_gram).await?;
+                            let res = query_3_grams::writer::insert_path(
+                                &result.three_gram_input,
+                                result.freq,
+                            )
+                            .and_then(|file_path| {
+                                let option =
+                                    query_3_grams::writer::WriteOptions::FILE(file_path.clone());
+                                query_3_grams::writer::write_insert(option, &result)?;
+                                Ok(file_path)
+                            });
+                            let file_path = match res {
+                                Ok(file_path) => file_path,
+                                Err(err) => {
+                                    eprintln!("{}", err);
+                                    return Ok(true);
+                                }
+                            };
+                            print!("\n{:?}", result);
+                            println!("This information can also be found in file:");
+                            println!("{}", file_path);
                         }
-                        print!("\n{:?}", result);
-                        println!("This information can also be found in file:");
-                        println!(
-                            "/home/projekt/query-results/insert/{}-{}-{}-{}",
-                            three_gram.word_1, three_gram.word_2, three_gram.word_3, result.freq
-                        );
                     }
                     Err(e) => {
                         eprintln!("{}", e);
-                        return Ok(());
+                        return Ok(true);
                     }
                 }
             }
@@ -215,23 +222,41 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 let input: Result<i32, _> = input.trim().parse();
 
                 if let Ok(input) = input {
+                    println!("\nYou can restrict the statistics with filters, for example:");
+                    println!("--from 2024-01-01T00:00:00 --to 2024-01-02T00:00:00 --pattern the* --run-id <id> --bucket 60");
+                    println!("Press enter to use all query results");
+                    print!("> ");
+                    io::stdout().flush().unwrap();
+
+                    let mut filter_input = String::new();
+                    io::stdin().read_line(&mut filter_input).unwrap();
+                    let filter_args: Vec<String> =
+                        filter_input.split_whitespace().map(String::from).collect();
+                    let filter = match stats::filter::StatsFilter::from(&filter_args) {
+                        Ok(filter) => filter,
+                        Err(err) => {
+                            eprintln!("{}", err);
+                            return Ok(true);
+                        }
+                    };
+
                     match input {
                         1 => {
                             println!("\nYou chose to get statistics about insert queries");
-                            let result = stats::create_insert_stats();
+                            let result = stats::create_insert_stats(&filter);
 
                             if let Err(err) = result {
                                 eprintln!("{}", err);
-                                return Ok(());
+                                return Ok(true);
                             }
                         }
                         2 => {
                             println!("\nYou chose to get statistics about select queries");
-                            let result = stats::create_select_stats();
+                            let result = stats::create_select_stats(&filter);
 
                             if let Err(err) = result {
                                 eprintln!("{}", err);
-                                return Ok(());
+                                return Ok(true);
                             }
                         }
                         _ => {
@@ -244,7 +269,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
             4 => {
                 println!("Exiting...");
-                return Ok(());
+                return Ok(false);
             }
             _ => {
                 println!("Invalid input");
@@ -253,5 +278,37 @@ async fn main() -> Result<(), Box<dyn Error>> {
     } else {
         println!("Invalid input");
     }
+    Ok(true)
+}
+
+/// Asks whether invalid lines should abort the run or be skipped, then queries
+/// every 3-gram of the input file and reports the skipped lines. The input
+/// format is detected from the file extension.
+async fn run_bulk(session: &Session, input_path: &str) -> Result<(), Box<dyn Error>> {
+    println!("\nHow should invalid lines be handled?");
+    println!("[1]: Stop at the first invalid line");
+    println!("[2]: Skip invalid lines and report them at the end");
+    print!("> ");
+    io::stdout().flush().unwrap();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    let mode = match input.trim().parse::<i32>() {
+        Ok(1) => reader::Mode::Strict,
+        Ok(2) => reader::Mode::Lenient,
+        _ => return Err("Invalid input".into()),
+    };
+
+    let mut three_grams = reader::open(input_path, None, mode, false)?;
+    let inputs = three_grams
+        .by_ref()
+        .map(|three_gram| three_gram.map(|three_gram| three_gram.input()));
+    let (run_id, count) = query_3_grams::get_bulk(session, inputs).await?;
+
+    println!("Run ID: {}", run_id);
+    println!("Queried {} three-grams", count);
+    reader::print_skipped(three_grams.errors());
+    println!("\nResults can be found in directory:");
+    println!("/home/projekt/query-results/select");
     Ok(())
 }
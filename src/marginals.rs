@@ -0,0 +1,170 @@
+use crate::corpus::{self, Corpus};
+use crate::query_3_grams::{scan, specs, statements};
+use futures::future::try_join_all;
+use scylla::Session;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+static TOTAL_NAME: &str = "three_grams";
+
+/// Creates the counter tables holding the marginals of the 3-grams of
+/// `corpus`: how often a word occurs at any position, and the total of all
+/// 3-gram frequencies. Pair counts come from the pair tables instead.
+pub async fn create(session: &Session, corpus: &Corpus) -> Result<(), Box<dyn Error>> {
+    for cql in [
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (word text PRIMARY KEY, freq counter)",
+            corpus.table("unigrams")
+        ),
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} (name text PRIMARY KEY, freq counter)",
+            corpus.table("totals")
+        ),
+    ] {
+        session.query(cql, ()).await?;
+    }
+    Ok(())
+}
+
+/// Adds `count` occurrences of the 3-gram to the marginals of the active
+/// corpus. Every position counts, so a 3-gram adds to three unigrams and
+/// probabilities divide by three times the total.
+pub async fn record(
+    session: &Session,
+    input: &specs::ThreeGramInput,
+    count: i64,
+) -> Result<(), Box<dyn Error>> {
+    let cqls = [
+        format!(
+            "UPDATE {} SET freq = freq + ? WHERE word = ?",
+            corpus::table("unigrams")
+        ),
+        format!(
+            "UPDATE {} SET freq = freq + ? WHERE name = ?",
+            corpus::table("totals")
+        ),
+    ];
+    let (unigram, total) = (cqls[0].as_str(), cqls[1].as_str());
+    let batch =
+        statements::prepare_counter_batch(session, &[unigram, unigram, unigram, total]).await?;
+
+    let (word_1, word_2, word_3) = (
+        input.word_1.as_str(),
+        input.word_2.as_str(),
+        input.word_3.as_str(),
+    );
+    session
+        .batch(
+            &batch,
+            (
+                (count, word_1),
+                (count, word_2),
+                (count, word_3),
+                (count, TOTAL_NAME),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+/// How often `word` occurs at any position of a 3-gram.
+pub async fn unigram(session: &Session, word: &str) -> Result<i64, Box<dyn Error>> {
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "SELECT freq FROM {} WHERE word = ?",
+            corpus::table("unigrams")
+        ),
+    )
+    .await?;
+    let row = session
+        .execute(&prepared, (word,))
+        .await?
+        .maybe_first_row_typed::<(i64,)>()?;
+    Ok(row.map(|(freq,)| freq).unwrap_or(0))
+}
+
+/// The sum of all 3-gram frequencies.
+pub async fn total(session: &Session) -> Result<i64, Box<dyn Error>> {
+    total_in(session, corpus::active()).await
+}
+
+/// Like `total`, but reads `corpus` instead of the active one.
+pub async fn total_in(session: &Session, corpus: &Corpus) -> Result<i64, Box<dyn Error>> {
+    let prepared = statements::prepare(
+        session,
+        &format!("SELECT freq FROM {} WHERE name = ?", corpus.table("totals")),
+    )
+    .await?;
+    let row = session
+        .execute(&prepared, (TOTAL_NAME,))
+        .await?
+        .maybe_first_row_typed::<(i64,)>()?;
+    Ok(row.map(|(freq,)| freq).unwrap_or(0))
+}
+
+/// Adds the counts of a batch of 3-grams to the marginals, one update per
+/// distinct word.
+async fn add_batch(session: &Session, batch: &[specs::ThreeGram]) -> Result<(), Box<dyn Error>> {
+    let mut unigrams: HashMap<&str, i64> = HashMap::new();
+    let mut total = 0;
+    for three_gram in batch {
+        let freq = three_gram.freq as i64;
+        for word in [&three_gram.word_1, &three_gram.word_2, &three_gram.word_3] {
+            *unigrams.entry(word.as_str()).or_default() += freq;
+        }
+        total += freq;
+    }
+
+    let unigram = statements::prepare(
+        session,
+        &format!(
+            "UPDATE {} SET freq = freq + ? WHERE word = ?",
+            corpus::table("unigrams")
+        ),
+    )
+    .await?;
+    let totals = statements::prepare(
+        session,
+        &format!(
+            "UPDATE {} SET freq = freq + ? WHERE name = ?",
+            corpus::table("totals")
+        ),
+    )
+    .await?;
+    try_join_all(
+        unigrams
+            .into_iter()
+            .map(|(word, freq)| session.execute(&unigram, (freq, word))),
+    )
+    .await?;
+    session.execute(&totals, (total, TOTAL_NAME)).await?;
+    Ok(())
+}
+
+/// Recomputes the marginals of the active corpus from `three_grams_1_2_pk`,
+/// e.g. for 3-grams stored before they were kept. The counters are emptied
+/// first, so nothing should be inserted while this runs. Returns the number
+/// of 3-grams counted.
+pub async fn rebuild(session: Session, parallelism: usize) -> Result<usize, Box<dyn Error>> {
+    for table in ["unigrams", "totals"] {
+        session
+            .query(format!("TRUNCATE {}", corpus::table(table)), ())
+            .await?;
+    }
+
+    let session = Arc::new(session);
+    let mut batches = scan::scan_all(session.clone(), parallelism);
+    let mut count = 0;
+    while let Some(batch) = batches.recv().await {
+        let batch = batch?;
+        add_batch(&session, &batch).await?;
+        count += batch.len();
+        print!("\rCounted {} three-grams", count);
+        io::stdout().flush()?;
+    }
+    println!();
+    Ok(count)
+}
@@ -0,0 +1,152 @@
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use core::fmt::Write;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+static LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    queries: BTreeMap::new(),
+    errors: BTreeMap::new(),
+});
+
+struct Histogram {
+    buckets: [u64; 12],
+    count: u64,
+    sum: f64,
+}
+
+/// Query counters, error counters and latency histograms collected since the
+/// process started. Queries are keyed by `(operation, table)`.
+struct Registry {
+    queries: BTreeMap<(String, String), Histogram>,
+    errors: BTreeMap<String, u64>,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: [0; 12],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, seconds: f64) {
+        for (index, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            if seconds <= *bound {
+                self.buckets[index] += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+pub fn record_query(operation: &str, table: &str, duration: Duration) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry
+        .queries
+        .entry((operation.to_string(), table.to_string()))
+        .or_insert_with(Histogram::new)
+        .observe(duration.as_secs_f64());
+}
+
+pub fn record_error(operation: &str) {
+    let mut registry = REGISTRY.lock().unwrap();
+    *registry.errors.entry(operation.to_string()).or_insert(0) += 1;
+}
+
+/// Renders all collected metrics in the Prometheus text exposition format.
+pub fn render() -> Result<String, core::fmt::Error> {
+    let registry = REGISTRY.lock().unwrap();
+    let mut output = String::new();
+
+    writeln!(
+        &mut output,
+        "# HELP three_gram_queries_total Number of queries executed."
+    )?;
+    writeln!(&mut output, "# TYPE three_gram_queries_total counter")?;
+    for ((operation, table), histogram) in &registry.queries {
+        writeln!(
+            &mut output,
+            "three_gram_queries_total{{operation=\"{}\",table=\"{}\"}} {}",
+            operation, table, histogram.count
+        )?;
+    }
+
+    writeln!(
+        &mut output,
+        "# HELP three_gram_query_errors_total Number of failed operations."
+    )?;
+    writeln!(&mut output, "# TYPE three_gram_query_errors_total counter")?;
+    for (operation, count) in &registry.errors {
+        writeln!(
+            &mut output,
+            "three_gram_query_errors_total{{operation=\"{}\"}} {}",
+            operation, count
+        )?;
+    }
+
+    writeln!(
+        &mut output,
+        "# HELP three_gram_query_duration_seconds Query latency in seconds."
+    )?;
+    writeln!(
+        &mut output,
+        "# TYPE three_gram_query_duration_seconds histogram"
+    )?;
+    for ((operation, table), histogram) in &registry.queries {
+        for (index, bound) in LATENCY_BUCKETS.iter().enumerate() {
+            writeln!(
+                &mut output,
+                "three_gram_query_duration_seconds_bucket{{operation=\"{}\",table=\"{}\",le=\"{}\"}} {}",
+                operation, table, bound, histogram.buckets[index]
+            )?;
+        }
+        writeln!(
+            &mut output,
+            "three_gram_query_duration_seconds_bucket{{operation=\"{}\",table=\"{}\",le=\"+Inf\"}} {}",
+            operation, table, histogram.count
+        )?;
+        writeln!(
+            &mut output,
+            "three_gram_query_duration_seconds_sum{{operation=\"{}\",table=\"{}\"}} {}",
+            operation, table, histogram.sum
+        )?;
+        writeln!(
+            &mut output,
+            "three_gram_query_duration_seconds_count{{operation=\"{}\",table=\"{}\"}} {}",
+            operation, table, histogram.count
+        )?;
+    }
+
+    Ok(output)
+}
+
+async fn metrics_handler() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render().unwrap_or_default(),
+    )
+}
+
+pub fn router() -> Router {
+    Router::new().route("/metrics", get(metrics_handler))
+}
+
+/// Serves `/metrics` on `addr` until the process exits.
+pub async fn serve(addr: SocketAddr) -> Result<(), Box<dyn Error + Send + Sync>> {
+    axum::Server::bind(&addr)
+        .serve(router().into_make_service())
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use crate::corpus;
+use crate::query_3_grams::{specs, statements};
+use scylla::Session;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::sync::OnceLock;
+use unicode_normalization::UnicodeNormalization;
+
+static ACTIVE: OnceLock<Pipeline> = OnceLock::new();
+static METADATA_KEY: &str = "normalization";
+
+pub enum Step {
+    Nfc,
+    Nfkc,
+    CaseFold,
+    StripPunctuation,
+    MaskDigits,
+    Map {
+        table: HashMap<String, String>,
+        checksum: u64,
+    },
+}
+
+/// Ordered normalization steps applied to every word before it is stored or
+/// looked up. Configured with a comma separated spec such as
+/// `nfkc,casefold,strip-punctuation,mask-digits,map=/path/to/table.tsv`.
+pub struct Pipeline {
+    steps: Vec<Step>,
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation()
+        || matches!(
+            c,
+            '\u{00A1}'
+                | '\u{00AB}'
+                | '\u{00BB}'
+                | '\u{00BF}'
+                | '\u{2010}'..='\u{2027}'
+                | '\u{2030}'..='\u{205E}'
+                | '\u{3000}'..='\u{303F}'
+        )
+}
+
+/// FNV-1a, used to record which mapping table a keyspace was built with.
+fn checksum(content: &str) -> u64 {
+    content.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn load_mapping(path: &str) -> Result<Step, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("Could not read mapping table {}: {}", path, err))?;
+    let mut table = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (from, to) = line.split_once('\t').ok_or(format!(
+            "Mapping table {} line {}: expected \"from<TAB>to\"",
+            path,
+            index + 1
+        ))?;
+        table.insert(from.to_string(), to.to_string());
+    }
+    Ok(Step::Map {
+        table,
+        checksum: checksum(&content),
+    })
+}
+
+impl Step {
+    fn apply(&self, word: String) -> String {
+        match self {
+            Step::Nfc => word.nfc().collect(),
+            Step::Nfkc => word.nfkc().collect(),
+            Step::CaseFold => word.to_lowercase(),
+            Step::StripPunctuation => word.chars().filter(|c| !is_punctuation(*c)).collect(),
+            Step::MaskDigits => word
+                .chars()
+                .map(|c| if c.is_numeric() { '#' } else { c })
+                .collect(),
+            Step::Map { table, .. } => table.get(&word).cloned().unwrap_or(word),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Step::Nfc => String::from("nfc"),
+            Step::Nfkc => String::from("nfkc"),
+            Step::CaseFold => String::from("casefold"),
+            Step::StripPunctuation => String::from("strip-punctuation"),
+            Step::MaskDigits => String::from("mask-digits"),
+            Step::Map { checksum, .. } => format!("map:{:016x}", checksum),
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn parse(spec: &str) -> Result<Pipeline, String> {
+        let mut steps = Vec::new();
+        for step in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+        {
+            let step = match step {
+                "none" => continue,
+                "nfc" => Step::Nfc,
+                "nfkc" => Step::Nfkc,
+                "casefold" => Step::CaseFold,
+                "strip-punctuation" => Step::StripPunctuation,
+                "mask-digits" => Step::MaskDigits,
+                _ => match step.strip_prefix("map=") {
+                    Some(path) => load_mapping(path)?,
+                    None => return Err(format!("Unknown normalization step: {}", step)),
+                },
+            };
+            steps.push(step);
+        }
+        Ok(Pipeline { steps })
+    }
+
+    /// Canonical description of the pipeline, stored in the keyspace metadata.
+    pub fn describe(&self) -> String {
+        if self.steps.is_empty() {
+            return String::from("none");
+        }
+        self.steps
+            .iter()
+            .map(Step::describe)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn normalize(&self, word: &str) -> String {
+        self.steps
+            .iter()
+            .fold(word.to_string(), |word, step| step.apply(word))
+    }
+
+    pub fn normalize_pair(&self, word_pair: &specs::WordPair) -> specs::WordPair {
+        specs::WordPair::new(
+            self.normalize(&word_pair.word_1),
+            self.normalize(&word_pair.word_2),
+        )
+    }
+
+    pub fn normalize_input(
+        &self,
+        input: &specs::ThreeGramInput,
+    ) -> Result<specs::ThreeGramInput, String> {
+        let words = [&input.word_1, &input.word_2, &input.word_3].map(|word| self.normalize(word));
+        if words.iter().any(|word| word.is_empty()) {
+            return Err(format!(
+                "\"{:?}\" is empty after normalization ({})",
+                input,
+                self.describe()
+            ));
+        }
+        let [word_1, word_2, word_3] = words;
+        Ok(specs::ThreeGramInput::new(word_1, word_2, word_3))
+    }
+}
+
+/// Sets the process-wide pipeline from `--normalize <spec>` or the
+/// `NORMALIZATION` environment variable and returns `args` without the flag.
+pub fn init(args: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut remaining = Vec::new();
+    let mut spec = std::env::var("NORMALIZATION").ok();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--normalize" {
+            spec = Some(iter.next().ok_or("--normalize expects a pipeline")?);
+        } else {
+            remaining.push(arg);
+        }
+    }
+
+    let pipeline = Pipeline::parse(spec.as_deref().unwrap_or("none"))?;
+    _ = ACTIVE.set(pipeline);
+    Ok(remaining)
+}
+
+pub fn active() -> &'static Pipeline {
+    ACTIVE.get_or_init(|| Pipeline { steps: Vec::new() })
+}
+
+/// Records the active pipeline in the metadata of the active corpus on first
+/// use and refuses to continue when the corpus was built with a different one.
+pub async fn verify(session: &Session) -> Result<(), Box<dyn Error>> {
+    let table = corpus::table("metadata");
+    session
+        .query(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {} (key text PRIMARY KEY, value text)",
+                table
+            ),
+            (),
+        )
+        .await?;
+    let prepared = statements::prepare(
+        session,
+        &format!("SELECT value FROM {} WHERE key = ?", table),
+    )
+    .await?;
+    let row = session
+        .execute(&prepared, (METADATA_KEY,))
+        .await?
+        .maybe_first_row_typed::<(String,)>()?;
+
+    let active = active().describe();
+    match row {
+        Some((recorded,)) if recorded != active => Err(format!(
+            "The corpus was built with normalization \"{}\" but the active pipeline is \"{}\"",
+            recorded, active
+        )
+        .into()),
+        Some(_) => Ok(()),
+        None => {
+            let prepared = statements::prepare(
+                session,
+                &format!(
+                    "INSERT INTO {} (key, value) VALUES (?, ?) IF NOT EXISTS",
+                    table
+                ),
+            )
+            .await?;
+            session.execute(&prepared, (METADATA_KEY, active)).await?;
+            Ok(())
+        }
+    }
+}
@@ -1,23 +1,48 @@
+use crate::corpus::{self, Corpus};
+use crate::leaderboard;
+use crate::marginals;
+use crate::metrics;
+use crate::normalize;
+use crate::trends;
 use crate::Error;
-use core::fmt::Write;
-use scylla::prepared_statement::PreparedStatement;
-use scylla::statement::Consistency;
+use itertools::Itertools;
 use scylla::IntoTypedRows;
 use std::collections::HashMap;
 use std::time::Instant;
+use uuid::Uuid;
 
+pub mod scan;
 pub mod specs;
+pub mod statements;
 pub mod writer;
 
 pub async fn get_3_gram(
     session: &scylla::Session,
     input: &specs::ThreeGramInput,
+    run_id: Option<&str>,
+) -> Result<specs::ThreeGramGetResult, Box<dyn Error>> {
+    let input = normalize::active().normalize_input(input)?;
+    let result = query_3_gram(session, &input, run_id).await;
+    if result.is_err() {
+        metrics::record_error("get");
+    }
+    result
+}
+
+async fn query_3_gram(
+    session: &scylla::Session,
+    input: &specs::ThreeGramInput,
+    run_id: Option<&str>,
 ) -> Result<specs::ThreeGramGetResult, Box<dyn Error>> {
     let start_time_one = Instant::now();
-    let mut prepared: PreparedStatement = session
-                .prepare("SELECT freq FROM n_grams.three_grams_1_2_pk WHERE word_1 = ? AND word_2 = ? AND word_3 = ?")
-                .await?;
-    prepared.set_consistency(Consistency::One);
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "SELECT freq FROM {} WHERE word_1 = ? AND word_2 = ? AND word_3 = ?",
+            corpus::table("three_grams_1_2_pk")
+        ),
+    )
+    .await?;
     let row = session
         .execute(
             &prepared,
@@ -37,128 +62,252 @@ pub async fn get_3_gram(
     let end_time_one = Instant::now();
     let duration_one = end_time_one - start_time_one;
 
-    let mut map_1_2 = HashMap::new();
-    let mut map_1_3 = HashMap::new();
-    let mut map_2_3 = HashMap::new();
     let start_time_all = Instant::now();
+    let result_1_2_pk = get_pair(
+        session,
+        specs::PairTable::FirstSecond,
+        specs::WordPair::new(input.word_1.clone(), input.word_2.clone()),
+    )
+    .await?;
+    let result_2_3_pk = get_pair(
+        session,
+        specs::PairTable::SecondThird,
+        specs::WordPair::new(input.word_2.clone(), input.word_3.clone()),
+    )
+    .await?;
+    let result_1_3_pk = get_pair(
+        session,
+        specs::PairTable::FirstThird,
+        specs::WordPair::new(input.word_1.clone(), input.word_3.clone()),
+    )
+    .await?;
+    let end_time_all = Instant::now();
+    let duration_all = end_time_all - start_time_all;
 
-    let mut prepared: PreparedStatement = session
-        .prepare(
-            "SELECT word_3, freq FROM n_grams.three_grams_1_2_pk WHERE word_1 = ? AND word_2 = ?",
-        )
-        .await?;
-    prepared.set_consistency(Consistency::One);
-    let rows_1_2 = session
-        .execute(&prepared, (input.word_1.clone(), input.word_2.clone()))
-        .await?
-        .rows;
+    metrics::record_query("get_exact", "three_grams_1_2_pk", duration_one);
 
-    let mut prepared: PreparedStatement = session
-        .prepare(
-            "SELECT word_1, freq FROM n_grams.three_grams_2_3_pk WHERE word_2 = ? AND word_3 = ?",
-        )
-        .await?;
-    prepared.set_consistency(Consistency::One);
-    let rows_2_3 = session
-        .execute(&prepared, (input.word_2.clone(), input.word_3.clone()))
-        .await?
-        .rows;
+    let three_gram_input = specs::ThreeGramInput::new(
+        input.word_1.clone(),
+        input.word_2.clone(),
+        input.word_3.clone(),
+    );
+    let result = specs::ThreeGramGetResult::new(
+        three_gram_input,
+        duration_all,
+        duration_one,
+        exact_freq,
+        Some(result_1_2_pk),
+        Some(result_1_3_pk),
+        Some(result_2_3_pk),
+    )
+    .with_run_id(run_id.map(String::from));
+    Ok(result)
+}
+
+/// Writes `result` to the select result directory, where the stats read it
+/// from, and returns the file path. Only local runs write result files; the
+/// servers return their results to the client instead.
+pub fn write_result(result: &specs::ThreeGramGetResult) -> Result<String, Box<dyn Error>> {
+    let path = writer::select_path(&result.three_gram_input)?;
+    writer::write_three_gram(writer::WriteOptions::FILE(path.clone()), result)?;
+    Ok(path)
+}
+
+/// Fetches every 3-gram continuing `word_pair` at the positions of
+/// `pair_table`, e.g. all `word_3` for a given `word_1 word_2`. Expects
+/// `word_pair` to be normalized already.
+pub async fn get_pair(
+    session: &scylla::Session,
+    pair_table: specs::PairTable,
+    word_pair: specs::WordPair,
+) -> Result<specs::QueryResult, Box<dyn Error>> {
+    get_pair_in(session, corpus::active(), pair_table, word_pair).await
+}
 
-    let mut prepared: PreparedStatement = session
-        .prepare(
-            "SELECT word_2, freq FROM n_grams.three_grams_1_3_pk WHERE word_1 = ? AND word_3 = ?",
+/// Like `get_pair`, but reads `corpus` instead of the active one.
+pub async fn get_pair_in(
+    session: &scylla::Session,
+    corpus: &Corpus,
+    pair_table: specs::PairTable,
+    word_pair: specs::WordPair,
+) -> Result<specs::QueryResult, Box<dyn Error>> {
+    let start_time = Instant::now();
+    let prepared = statements::prepare(session, &pair_table.select_cql(corpus)).await?;
+    let rows = session
+        .execute(
+            &prepared,
+            (word_pair.word_1.clone(), word_pair.word_2.clone()),
         )
-        .await?;
-    prepared.set_consistency(Consistency::One);
-    let rows_1_3 = session
-        .execute(&prepared, (input.word_1.clone(), input.word_3.clone()))
         .await?
         .rows;
+    let duration = Instant::now() - start_time;
+    metrics::record_query("get_pair", pair_table.table(), duration);
 
-    let end_time_all = Instant::now();
-    let duration_all = end_time_all - start_time_all;
-
-    if let Some(rows) = rows_1_2 {
-        for row in rows.into_typed::<(String, i32)>() {
-            let (word_3, freq): (String, i32) = row?;
-            map_1_2.insert(word_3, freq);
-        }
-    }
-    if let Some(rows) = rows_1_3 {
+    let mut word_pair_map = HashMap::new();
+    if let Some(rows) = rows {
         for row in rows.into_typed::<(String, i32)>() {
-            let (word_2, freq): (String, i32) = row?;
-            map_1_3.insert(word_2, freq);
+            let (word, freq): (String, i32) = row?;
+            word_pair_map.insert(word, freq);
         }
     }
-    if let Some(rows) = rows_2_3 {
-        for row in rows.into_typed::<(String, i32)>() {
-            let (word_1, freq): (String, i32) = row?;
-            map_2_3.insert(word_1, freq);
+    Ok(specs::QueryResult::new(word_pair, word_pair_map, duration))
+}
+
+/// Returns the `limit` most frequent words following the normalized
+/// `word_pair`, with their share of all continuations of the pair.
+pub async fn predict_next(
+    session: &scylla::Session,
+    word_pair: specs::WordPair,
+    limit: usize,
+) -> Result<Vec<specs::Prediction>, Box<dyn Error>> {
+    let result = get_pair(session, specs::PairTable::FirstSecond, word_pair).await?;
+    let total: i64 = result.word_pair_map.values().map(|freq| *freq as i64).sum();
+    let predictions = result
+        .word_pair_map
+        .into_iter()
+        .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+        .take(limit)
+        .map(|(word, freq)| specs::Prediction {
+            word,
+            freq,
+            probability: freq as f64 / total as f64,
+        })
+        .collect();
+    Ok(predictions)
+}
+
+/// Runs `get_3_gram` for every input and writes the results, tagging each
+/// with a shared run ID so the stats can later be restricted to this run.
+/// Stops at the first input error. Returns the run ID and the number of 3-grams queried.
+pub async fn get_bulk<I, E>(
+    session: &scylla::Session,
+    inputs: I,
+) -> Result<(String, usize), Box<dyn Error>>
+where
+    I: IntoIterator<Item = Result<specs::ThreeGramInput, E>>,
+    E: Error + 'static,
+{
+    let run_id = Uuid::new_v4().to_string();
+    let mut count = 0;
+    for input in inputs {
+        let input = input?;
+        let result = get_3_gram(session, &input, Some(&run_id)).await?;
+        if let Err(err) = write_result(&result) {
+            eprintln!("{}", err);
         }
+        count += 1;
     }
-    let three_gram_input = specs::ThreeGramInput::new(
-        input.word_1.clone(),
-        input.word_2.clone(),
-        input.word_3.clone(),
-    );
-    let result_1_2_pk = Some(specs::QueryResult::new(
-        specs::WordPair::new(input.word_1.clone(), input.word_2.clone()),
-        map_1_2,
-    ));
-    let result_1_3_pk = Some(specs::QueryResult::new(
-        specs::WordPair::new(input.word_1.clone(), input.word_3.clone()),
-        map_1_3,
-    ));
-    let result_2_3_pk = Some(specs::QueryResult::new(
-        specs::WordPair::new(input.word_2.clone(), input.word_3.clone()),
-        map_2_3,
-    ));
-    let result = specs::ThreeGramGetResult {
-        three_gram_input,
-        time_taken_all: duration_all,
-        time_taken_one: duration_one,
-        exact_freq,
-        result_1_2_pk,
-        result_1_3_pk,
-        result_2_3_pk,
-    };
-    let mut absolute_path = String::new();
-    write!(
-        &mut absolute_path,
-        "/home/projekt/query-results/select/{}-{}-{}",
-        input.word_1.clone(),
-        input.word_2.clone(),
-        input.word_3.clone(),
-    )?;
-    let option = writer::WriteOptions::FILE(absolute_path);
-    let res = writer::write_three_gram(option, &result);
-    match res {
-        Ok(()) => (),
-        Err(err) => eprintln!("{}", err),
+    Ok((run_id, count))
+}
+
+/// Inserts every 3-gram, adding its count to the stored frequency, and writes
+/// the results to the insert result directory tagged with a shared run ID.
+/// Stops at the first input error. Returns the run ID and the number of
+/// 3-grams inserted.
+pub async fn insert_bulk<I, E>(
+    session: &scylla::Session,
+    three_grams: I,
+) -> Result<(String, usize), Box<dyn Error>>
+where
+    I: IntoIterator<Item = Result<specs::ThreeGram, E>>,
+    E: Error + 'static,
+{
+    let run_id = Uuid::new_v4().to_string();
+    let mut count = 0;
+    for three_gram in three_grams {
+        let three_gram = three_gram?;
+        let result = insert_count(session, &three_gram.input(), three_gram.freq)
+            .await?
+            .with_run_id(Some(run_id.clone()));
+        let file_path = writer::insert_path(&result.three_gram_input, result.freq)?;
+        writer::write_insert(writer::WriteOptions::FILE(file_path), &result)?;
+        count += 1;
     }
-    Ok(result)
+    Ok((run_id, count))
+}
+
+/// Returns the stored frequency of the 3-gram, 0 when it is unknown. The input
+/// is used as given, without normalization.
+pub async fn get_freq(
+    session: &scylla::Session,
+    input: &specs::ThreeGramInput,
+) -> Result<i32, Box<dyn Error>> {
+    get_freq_in(session, corpus::active(), input).await
+}
+
+/// Like `get_freq`, but reads `corpus` instead of the active one.
+pub async fn get_freq_in(
+    session: &scylla::Session,
+    corpus: &Corpus,
+    input: &specs::ThreeGramInput,
+) -> Result<i32, Box<dyn Error>> {
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "SELECT freq FROM {} WHERE word_1 = ? AND word_2 = ? AND word_3 = ?",
+            corpus.table("three_grams_1_2_pk")
+        ),
+    )
+    .await?;
+    let row = session
+        .execute(
+            &prepared,
+            (
+                input.word_1.as_str(),
+                input.word_2.as_str(),
+                input.word_3.as_str(),
+            ),
+        )
+        .await?
+        .maybe_first_row_typed::<(i32,)>()?;
+    Ok(row.map(|(freq,)| freq).unwrap_or(0))
 }
 
-pub async fn get_bulk(
+/// Sets the frequency of the 3-gram in all three tables in one logged batch,
+/// so the tables can't drift apart when a write fails halfway.
+pub async fn write_freq(
     session: &scylla::Session,
-    inputs: &Vec<specs::ThreeGramInput>,
+    input: &specs::ThreeGramInput,
+    freq: i32,
 ) -> Result<(), Box<dyn Error>> {
-    for input in inputs {
-        _ = get_3_gram(&session, input).await?;
-    }
+    let cqls: Vec<String> = [
+        "three_grams_1_2_pk",
+        "three_grams_1_3_pk",
+        "three_grams_2_3_pk",
+    ]
+    .iter()
+    .map(|table| {
+        format!(
+            "INSERT INTO {} (word_1, word_2, word_3, freq) VALUES (?, ?, ?, ?)",
+            corpus::table(table)
+        )
+    })
+    .collect();
+    let cqls: Vec<&str> = cqls.iter().map(String::as_str).collect();
+    let batch = statements::prepare_batch(session, &cqls).await?;
+    let values = (
+        input.word_1.as_str(),
+        input.word_2.as_str(),
+        input.word_3.as_str(),
+        freq,
+    );
+    session.batch(&batch, (values, values, values)).await?;
     Ok(())
 }
 
 pub async fn insert_new(
     session: &scylla::Session,
     input: &specs::ThreeGramInput,
+    freq: i32,
 ) -> Result<(), Box<dyn Error>> {
-    let mut prepared: PreparedStatement = session
-        .prepare(
-            "INSERT INTO n_grams.three_grams_1_2_pk (word_1, word_2, word_3, freq) VALUES (?, ?, ?, ?)",
-        )
-        .await?;
-    prepared.set_consistency(Consistency::One);
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "INSERT INTO {} (word_1, word_2, word_3, freq) VALUES (?, ?, ?, ?)",
+            corpus::table("three_grams_1_2_pk")
+        ),
+    )
+    .await?;
     session
         .execute(
             &prepared,
@@ -166,17 +315,19 @@ pub async fn insert_new(
                 input.word_1.clone(),
                 input.word_2.clone(),
                 input.word_3.clone(),
-                1,
+                freq,
             ),
         )
         .await?;
 
-    let mut prepared: PreparedStatement = session
-    .prepare(
-        "INSERT INTO n_grams.three_grams_1_3_pk (word_1, word_2, word_3, freq) VALUES (?, ?, ?, ?)",
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "INSERT INTO {} (word_1, word_2, word_3, freq) VALUES (?, ?, ?, ?)",
+            corpus::table("three_grams_1_3_pk")
+        ),
     )
     .await?;
-    prepared.set_consistency(Consistency::One);
     session
         .execute(
             &prepared,
@@ -184,17 +335,19 @@ pub async fn insert_new(
                 input.word_1.clone(),
                 input.word_2.clone(),
                 input.word_3.clone(),
-                1,
+                freq,
             ),
         )
         .await?;
 
-    let mut prepared: PreparedStatement = session
-    .prepare(
-        "INSERT INTO n_grams.three_grams_2_3_pk (word_1, word_2, word_3, freq) VALUES (?, ?, ?, ?)",
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "INSERT INTO {} (word_1, word_2, word_3, freq) VALUES (?, ?, ?, ?)",
+            corpus::table("three_grams_2_3_pk")
+        ),
     )
     .await?;
-    prepared.set_consistency(Consistency::One);
     session
         .execute(
             &prepared,
@@ -202,7 +355,7 @@ pub async fn insert_new(
                 input.word_1.clone(),
                 input.word_2.clone(),
                 input.word_3.clone(),
-                1,
+                freq,
             ),
         )
         .await?;
@@ -213,14 +366,17 @@ pub async fn insert_new(
 pub async fn update_one(
     session: &scylla::Session,
     input: &specs::ThreeGram,
+    count: i32,
 ) -> Result<(), Box<dyn Error>> {
-    let freq = input.freq + 1;
-    let mut prepared: PreparedStatement = session
-    .prepare(
-        "UPDATE n_grams.three_grams_1_2_pk SET freq = ? WHERE word_1 = ? AND word_2 = ? AND word_3 = ?",
+    let freq = input.freq + count;
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "UPDATE {} SET freq = ? WHERE word_1 = ? AND word_2 = ? AND word_3 = ?",
+            corpus::table("three_grams_1_2_pk")
+        ),
     )
     .await?;
-    prepared.set_consistency(Consistency::One);
     session
         .execute(
             &prepared,
@@ -233,12 +389,14 @@ pub async fn update_one(
         )
         .await?;
 
-    let mut prepared: PreparedStatement = session
-    .prepare(
-        "UPDATE n_grams.three_grams_1_3_pk SET freq = ? WHERE word_1 = ? AND word_2 = ? AND word_3 = ?",
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "UPDATE {} SET freq = ? WHERE word_1 = ? AND word_2 = ? AND word_3 = ?",
+            corpus::table("three_grams_1_3_pk")
+        ),
     )
     .await?;
-    prepared.set_consistency(Consistency::One);
     session
         .execute(
             &prepared,
@@ -251,12 +409,14 @@ pub async fn update_one(
         )
         .await?;
 
-    let mut prepared: PreparedStatement = session
-    .prepare(
-        "UPDATE n_grams.three_grams_2_3_pk SET freq = ? WHERE word_1 = ? AND word_2 = ? AND word_3 = ?",
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "UPDATE {} SET freq = ? WHERE word_1 = ? AND word_2 = ? AND word_3 = ?",
+            corpus::table("three_grams_2_3_pk")
+        ),
     )
     .await?;
-    prepared.set_consistency(Consistency::One);
     session
         .execute(
             &prepared,
@@ -274,12 +434,49 @@ pub async fn update_one(
 pub async fn insert(
     session: &scylla::Session,
     input: &specs::ThreeGramInput,
+) -> Result<specs::ThreeGramInsertResult, Box<dyn Error>> {
+    insert_count(session, input, 1).await
+}
+
+/// Like `insert`, but adds `count` to the frequency instead of 1.
+pub async fn insert_count(
+    session: &scylla::Session,
+    input: &specs::ThreeGramInput,
+    count: i32,
+) -> Result<specs::ThreeGramInsertResult, Box<dyn Error>> {
+    let input = normalize::active().normalize_input(input)?;
+    // Unwrap the result before the next await: the boxed error isn't Send and
+    // would make the future unusable from the HTTP and gRPC handlers.
+    let result = match insert_or_increment(session, &input, count).await {
+        Ok(result) => result,
+        Err(err) => {
+            metrics::record_error("insert");
+            return Err(err);
+        }
+    };
+    metrics::record_query("insert", "all", result.time_taken);
+    marginals::record(session, &input, count as i64).await?;
+    leaderboard::record(session, &input, result.freq - count, result.freq).await?;
+    if trends::enabled() {
+        trends::record(session, &input, count).await?;
+    }
+    Ok(result)
+}
+
+async fn insert_or_increment(
+    session: &scylla::Session,
+    input: &specs::ThreeGramInput,
+    count: i32,
 ) -> Result<specs::ThreeGramInsertResult, Box<dyn Error>> {
     let start_time = Instant::now();
-    let mut prepared: PreparedStatement = session.prepare(
-    "SELECT * FROM n_grams.three_grams_1_2_pk WHERE word_1 = ? AND word_2 = ? AND word_3 = ?",
-).await?;
-    prepared.set_consistency(Consistency::One);
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "SELECT * FROM {} WHERE word_1 = ? AND word_2 = ? AND word_3 = ?",
+            corpus::table("three_grams_1_2_pk")
+        ),
+    )
+    .await?;
     let row = session
         .execute(
             &prepared,
@@ -301,22 +498,22 @@ pub async fn insert(
     if let Some(row) = row {
         let (word_1, word_2, word_3, freq): (String, String, String, i32) = row;
         let three_gram = specs::ThreeGram::new(word_1, word_2, word_3, freq);
-        update_one(&session, &three_gram).await?;
+        update_one(session, &three_gram, count).await?;
         let end_time = Instant::now();
         let duration = end_time - start_time;
-        return Ok(specs::ThreeGramInsertResult::new(
+        Ok(specs::ThreeGramInsertResult::new(
             three_gram_input,
             duration,
-            freq + 1,
-        ));
+            freq + count,
+        ))
     } else {
-        insert_new(&session, &input).await?;
+        insert_new(session, input, count).await?;
         let end_time = Instant::now();
         let duration = end_time - start_time;
-        return Ok(specs::ThreeGramInsertResult::new(
+        Ok(specs::ThreeGramInsertResult::new(
             three_gram_input,
             duration,
-            1,
-        ));
+            count,
+        ))
     }
 }
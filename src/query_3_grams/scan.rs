@@ -0,0 +1,91 @@
+use super::{specs, statements};
+use crate::corpus::{self, Corpus};
+use futures::StreamExt;
+use scylla::Session;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+static PAGE_SIZE: i32 = 5000;
+
+pub type Batch = Result<Vec<specs::ThreeGram>, String>;
+
+/// Splits the Murmur3 token ring into `count` contiguous, inclusive ranges.
+pub fn token_ranges(count: usize) -> Vec<(i64, i64)> {
+    let count = count.max(1) as i128;
+    let width = (i64::MAX as i128 - i64::MIN as i128 + 1) / count;
+    (0..count)
+        .map(|index| {
+            let start = i64::MIN as i128 + index * width;
+            let end = if index == count - 1 {
+                i64::MAX as i128
+            } else {
+                start + width - 1
+            };
+            (start as i64, end as i64)
+        })
+        .collect()
+}
+
+async fn scan_range(
+    session: &Session,
+    corpus: &Corpus,
+    (start, end): (i64, i64),
+    sender: &mpsc::Sender<Batch>,
+) -> Result<(), String> {
+    let cql = format!(
+        "SELECT word_1, word_2, word_3, freq FROM {} WHERE token(word_1, word_2) >= ? AND token(word_1, word_2) <= ?",
+        corpus.table("three_grams_1_2_pk")
+    );
+    let mut prepared = statements::prepare(session, &cql)
+        .await
+        .map_err(|err| err.to_string())?;
+    prepared.set_page_size(PAGE_SIZE);
+    let mut rows = session
+        .execute_iter(prepared, (start, end))
+        .await
+        .map_err(|err| err.to_string())?
+        .into_typed::<(String, String, String, i32)>();
+
+    let mut batch = Vec::new();
+    while let Some(row) = rows.next().await {
+        let (word_1, word_2, word_3, freq) = row.map_err(|err| err.to_string())?;
+        batch.push(specs::ThreeGram::new(word_1, word_2, word_3, freq));
+        if batch.len() == PAGE_SIZE as usize {
+            if sender.send(Ok(batch)).await.is_err() {
+                return Ok(());
+            }
+            batch = Vec::new();
+        }
+    }
+    if !batch.is_empty() {
+        _ = sender.send(Ok(batch)).await;
+    }
+    Ok(())
+}
+
+/// Pages through all of `three_grams_1_2_pk`, scanning `parallelism` token
+/// ranges concurrently. Rows arrive in batches, in no particular order; a
+/// failed range sends its error and stops.
+pub fn scan_all(session: Arc<Session>, parallelism: usize) -> mpsc::Receiver<Batch> {
+    scan_corpus(session, corpus::active().clone(), parallelism)
+}
+
+/// Like `scan_all`, but pages through `corpus` instead of the active one.
+pub fn scan_corpus(
+    session: Arc<Session>,
+    corpus: Corpus,
+    parallelism: usize,
+) -> mpsc::Receiver<Batch> {
+    let (sender, receiver) = mpsc::channel(parallelism.max(1) * 2);
+    for range in token_ranges(parallelism) {
+        let session = session.clone();
+        let corpus = corpus.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Err(err) = scan_range(&session, &corpus, range, &sender).await {
+                _ = sender.send(Err(err)).await;
+            }
+        });
+    }
+    receiver
+}
@@ -1,10 +1,21 @@
+use crate::corpus::Corpus;
+use crate::tokenizer;
+use chrono::{DateTime, SecondsFormat, Utc};
 use core::fmt::{self, Write};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize, Serializer};
 use std::collections::HashMap;
 use std::time::Duration;
 
 static DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT: usize = 10;
 
+/// Durations are exposed as fractional seconds in JSON, like in the result
+/// files.
+fn serialize_seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ThreeGram {
     pub word_1: String,
     pub word_2: String,
@@ -12,36 +23,66 @@ pub struct ThreeGram {
     pub freq: i32,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct ThreeGramInput {
     pub word_1: String,
     pub word_2: String,
     pub word_3: String,
 }
 
+#[derive(Serialize)]
 pub struct ThreeGramInsertResult {
     pub three_gram_input: ThreeGramInput,
+    #[serde(serialize_with = "serialize_seconds")]
     pub time_taken: Duration,
     pub freq: i32,
+    pub timestamp: DateTime<Utc>,
+    pub run_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Prediction {
+    pub word: String,
+    pub freq: i32,
+    pub probability: f64,
+}
+
+/// The three tables keyed by a pair of words, named after the positions of
+/// the pair in the 3-gram.
+#[derive(Clone, Copy)]
+pub enum PairTable {
+    FirstSecond,
+    FirstThird,
+    SecondThird,
 }
 
+#[derive(Clone, Serialize)]
 pub struct WordPair {
     pub word_1: String,
     pub word_2: String,
 }
 
+#[derive(Serialize)]
 pub struct QueryResult {
     pub word_pair: WordPair,
     pub word_pair_map: HashMap<String, i32>,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub time_taken: Duration,
 }
 
+#[derive(Serialize)]
 pub struct ThreeGramGetResult {
     pub three_gram_input: ThreeGramInput,
+    #[serde(serialize_with = "serialize_seconds")]
     pub time_taken_all: Duration,
+    #[serde(serialize_with = "serialize_seconds")]
     pub time_taken_one: Duration,
     pub exact_freq: i32,
     pub result_1_2_pk: Option<QueryResult>,
     pub result_1_3_pk: Option<QueryResult>,
     pub result_2_3_pk: Option<QueryResult>,
+    pub timestamp: DateTime<Utc>,
+    pub run_id: Option<String>,
 }
 
 impl ThreeGram {
@@ -53,6 +94,14 @@ impl ThreeGram {
             freq,
         }
     }
+
+    pub fn input(&self) -> ThreeGramInput {
+        ThreeGramInput::new(
+            self.word_1.clone(),
+            self.word_2.clone(),
+            self.word_3.clone(),
+        )
+    }
 }
 
 impl ThreeGramInput {
@@ -65,18 +114,39 @@ impl ThreeGramInput {
     }
 
     pub fn from(input: String) -> Result<ThreeGramInput, String> {
-        let words: Vec<&str> = input.trim().split_whitespace().collect();
+        let words = tokenizer::active().tokenize(&input);
 
         if words.len() != 3 {
             return Err("Input must contain 3 words".to_string());
         }
 
-        let word_1 = String::from(words[0]);
-        let word_2 = String::from(words[1]);
-        let word_3 = String::from(words[2]);
+        let word_1 = words[0].clone();
+        let word_2 = words[1].clone();
+        let word_3 = words[2].clone();
 
         Ok(ThreeGramInput::new(word_1, word_2, word_3))
     }
+
+    /// Tokenizes `input` and returns every 3-gram of consecutive words in it.
+    pub fn from_text(input: &str) -> Result<Vec<ThreeGramInput>, String> {
+        let words = tokenizer::active().tokenize(input);
+
+        if words.len() < 3 {
+            return Err("Input must contain at least 3 words".to_string());
+        }
+
+        Ok(ThreeGramInput::from_words(&words))
+    }
+
+    /// Returns every 3-gram of consecutive words in `words`.
+    pub fn from_words(words: &[String]) -> Vec<ThreeGramInput> {
+        words
+            .windows(3)
+            .map(|window| {
+                ThreeGramInput::new(window[0].clone(), window[1].clone(), window[2].clone())
+            })
+            .collect()
+    }
 }
 
 impl ThreeGramInsertResult {
@@ -89,8 +159,42 @@ impl ThreeGramInsertResult {
             three_gram_input,
             time_taken,
             freq,
+            timestamp: Utc::now(),
+            run_id: None,
+        }
+    }
+
+    /// Tags the result with the run it belongs to, so the stats can be
+    /// restricted to that run.
+    pub fn with_run_id(mut self, run_id: Option<String>) -> ThreeGramInsertResult {
+        self.run_id = run_id;
+        self
+    }
+}
+
+impl PairTable {
+    pub fn table(&self) -> &'static str {
+        match self {
+            PairTable::FirstSecond => "three_grams_1_2_pk",
+            PairTable::FirstThird => "three_grams_1_3_pk",
+            PairTable::SecondThird => "three_grams_2_3_pk",
         }
     }
+
+    pub fn select_cql(&self, corpus: &Corpus) -> String {
+        let (free, key_1, key_2) = match self {
+            PairTable::FirstSecond => ("word_3", "word_1", "word_2"),
+            PairTable::FirstThird => ("word_2", "word_1", "word_3"),
+            PairTable::SecondThird => ("word_1", "word_2", "word_3"),
+        };
+        format!(
+            "SELECT {}, freq FROM {} WHERE {} = ? AND {} = ?",
+            free,
+            corpus.table(self.table()),
+            key_1,
+            key_2
+        )
+    }
 }
 
 impl WordPair {
@@ -100,12 +204,21 @@ impl WordPair {
 }
 
 impl QueryResult {
-    pub fn new(word_pair: WordPair, word_pair_map: HashMap<String, i32>) -> QueryResult {
+    pub fn new(
+        word_pair: WordPair,
+        word_pair_map: HashMap<String, i32>,
+        time_taken: Duration,
+    ) -> QueryResult {
         QueryResult {
             word_pair,
             word_pair_map,
+            time_taken,
         }
     }
+
+    pub fn row_count(&self) -> usize {
+        self.word_pair_map.len()
+    }
 }
 
 impl ThreeGramGetResult {
@@ -126,8 +239,17 @@ impl ThreeGramGetResult {
             result_1_2_pk,
             result_1_3_pk,
             result_2_3_pk,
+            timestamp: Utc::now(),
+            run_id: None,
         }
     }
+
+    /// Tags the result with the run it belongs to, so the stats can be
+    /// restricted to that run.
+    pub fn with_run_id(mut self, run_id: Option<String>) -> ThreeGramGetResult {
+        self.run_id = run_id;
+        self
+    }
 }
 
 impl fmt::Debug for ThreeGramInput {
@@ -154,9 +276,9 @@ impl fmt::Debug for ThreeGramInsertResult {
         let third_word = &self.three_gram_input.word_3;
         let time_taken = &self.time_taken;
         let freq = &self.freq;
-        write!(
+        writeln!(
             &mut result_string,
-            "Inserted 3-gram: {} {} {} = {} in {}.{:03} seconds\n",
+            "Inserted 3-gram: {} {} {} = {} in {}.{:03} seconds",
             first_word,
             second_word,
             third_word,
@@ -164,6 +286,14 @@ impl fmt::Debug for ThreeGramInsertResult {
             time_taken.as_secs(),
             time_taken.subsec_millis()
         )?;
+        writeln!(
+            &mut result_string,
+            "Timestamp: {}",
+            self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+        )?;
+        if let Some(run_id) = &self.run_id {
+            writeln!(&mut result_string, "Run ID: {}", run_id)?;
+        }
         write!(f, "{}", result_string)
     }
 }
@@ -177,35 +307,50 @@ impl fmt::Debug for ThreeGramGetResult {
         let time_taken_all = &self.time_taken_all;
         let time_taken_one = &self.time_taken_one;
         let exact_freq = &self.exact_freq;
-        write!(
+        writeln!(
             &mut result_string,
-            "Given 3-gram: {} {} {} = {}\n",
+            "Given 3-gram: {} {} {} = {}",
             first_word_input, second_word_input, third_word_input, exact_freq
         )?;
-        write!(
+        writeln!(
+            &mut result_string,
+            "Timestamp: {}",
+            self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+        )?;
+        if let Some(run_id) = &self.run_id {
+            writeln!(&mut result_string, "Run ID: {}", run_id)?;
+        }
+        writeln!(
             &mut result_string,
-            "Time taken to get the exact frequency: {}.{:03} seconds\n",
+            "Time taken to get the exact frequency: {}.{:03} seconds",
             time_taken_one.as_secs(),
             time_taken_one.subsec_millis()
         )?;
-        write!(
+        writeln!(
             &mut result_string,
-            "Time taken to get all values: {}.{:03} seconds\n",
+            "Time taken to get all values: {}.{:03} seconds",
             time_taken_all.as_secs(),
             time_taken_all.subsec_millis()
         )?;
         if let Some(result_1_2_pk) = &self.result_1_2_pk {
             let first_word = &result_1_2_pk.word_pair.word_1;
             let second_word = &result_1_2_pk.word_pair.word_2;
-            write!(
+            writeln!(
                 &mut result_string,
-                "--- query executed based on first and second word ---\n"
+                "--- query executed based on first and second word ---"
             )?;
-            write!(
+            writeln!(
                 &mut result_string,
-                "words: {} {} _____\n",
+                "words: {} {} _____",
                 first_word, second_word
             )?;
+            writeln!(
+                &mut result_string,
+                "Time taken for pair query: {}.{:03} seconds ({} rows)",
+                result_1_2_pk.time_taken.as_secs(),
+                result_1_2_pk.time_taken.subsec_millis(),
+                result_1_2_pk.row_count()
+            )?;
             let count = result_1_2_pk.word_pair_map.len();
             let top_10_elements: Vec<_> = result_1_2_pk
                 .word_pair_map
@@ -213,28 +358,35 @@ impl fmt::Debug for ThreeGramGetResult {
                 .collect::<Vec<_>>()
                 .into_iter()
                 .sorted_by(|a, b| b.1.cmp(a.1))
-                .take(DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT as usize)
+                .take(DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT)
                 .collect();
             for (word, frequency) in top_10_elements {
-                write!(&mut result_string, " {}: {}\n", word, frequency)?;
+                writeln!(&mut result_string, " {}: {}", word, frequency)?;
             }
             if count > DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT {
                 let remaining_count = count - DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT;
-                write!(&mut result_string, " ... and {} more\n", remaining_count)?;
+                writeln!(&mut result_string, " ... and {} more", remaining_count)?;
             }
         }
         if let Some(result_1_3_pk) = &self.result_1_3_pk {
             let first_word = &result_1_3_pk.word_pair.word_1;
             let third_word = &result_1_3_pk.word_pair.word_2;
-            write!(
+            writeln!(
                 &mut result_string,
-                "--- query executed based on first and third word ---\n"
+                "--- query executed based on first and third word ---"
             )?;
-            write!(
+            writeln!(
                 &mut result_string,
-                "words: {} _____ {}\n",
+                "words: {} _____ {}",
                 first_word, third_word
             )?;
+            writeln!(
+                &mut result_string,
+                "Time taken for pair query: {}.{:03} seconds ({} rows)",
+                result_1_3_pk.time_taken.as_secs(),
+                result_1_3_pk.time_taken.subsec_millis(),
+                result_1_3_pk.row_count()
+            )?;
             let count = result_1_3_pk.word_pair_map.len();
             let top_10_elements: Vec<_> = result_1_3_pk
                 .word_pair_map
@@ -242,28 +394,35 @@ impl fmt::Debug for ThreeGramGetResult {
                 .collect::<Vec<_>>()
                 .into_iter()
                 .sorted_by(|a, b| b.1.cmp(a.1))
-                .take(DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT as usize)
+                .take(DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT)
                 .collect();
             for (word, frequency) in top_10_elements {
-                write!(&mut result_string, " {}: {}\n", word, frequency)?;
+                writeln!(&mut result_string, " {}: {}", word, frequency)?;
             }
             if count > DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT {
                 let remaining_count = count - DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT;
-                write!(&mut result_string, " ... and {} more\n", remaining_count)?;
+                writeln!(&mut result_string, " ... and {} more", remaining_count)?;
             }
         }
         if let Some(result_2_3_pk) = &self.result_2_3_pk {
             let second_word = &result_2_3_pk.word_pair.word_1;
             let third_word = &result_2_3_pk.word_pair.word_2;
-            write!(
+            writeln!(
                 &mut result_string,
-                "--- query executed based on second and third word ---\n"
+                "--- query executed based on second and third word ---"
             )?;
-            write!(
+            writeln!(
                 &mut result_string,
-                "words: _____ {} {}\n",
+                "words: _____ {} {}",
                 second_word, third_word
             )?;
+            writeln!(
+                &mut result_string,
+                "Time taken for pair query: {}.{:03} seconds ({} rows)",
+                result_2_3_pk.time_taken.as_secs(),
+                result_2_3_pk.time_taken.subsec_millis(),
+                result_2_3_pk.row_count()
+            )?;
             let count = result_2_3_pk.word_pair_map.len();
             let top_10_elements: Vec<_> = result_2_3_pk
                 .word_pair_map
@@ -271,14 +430,14 @@ impl fmt::Debug for ThreeGramGetResult {
                 .collect::<Vec<_>>()
                 .into_iter()
                 .sorted_by(|a, b| b.1.cmp(a.1))
-                .take(DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT as usize)
+                .take(DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT)
                 .collect();
             for (word, frequency) in top_10_elements {
-                write!(&mut result_string, " {}: {}\n", word, frequency)?;
+                writeln!(&mut result_string, " {}: {}", word, frequency)?;
             }
             if count > DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT {
                 let remaining_count = count - DEFAULT_NUMBER_OF_3_GRAMS_TO_PRINT;
-                write!(&mut result_string, " ... and {} more\n", remaining_count)?;
+                writeln!(&mut result_string, " ... and {} more", remaining_count)?;
             }
         }
         write!(f, "{}", result_string)
@@ -0,0 +1,50 @@
+use scylla::batch::{Batch, BatchType};
+use scylla::prepared_statement::PreparedStatement;
+use scylla::statement::Consistency;
+use scylla::transport::errors::QueryError;
+use scylla::Session;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Statements already prepared by this process, keyed by their CQL. The tool
+/// only ever talks to one cluster, so one cache is shared by every session.
+static CACHE: Mutex<BTreeMap<String, PreparedStatement>> = Mutex::new(BTreeMap::new());
+
+/// Prepares `cql` with consistency ONE, reusing the statement prepared by an
+/// earlier call with the same CQL.
+pub async fn prepare(session: &Session, cql: &str) -> Result<PreparedStatement, QueryError> {
+    let cached = CACHE.lock().unwrap().get(cql).cloned();
+    if let Some(prepared) = cached {
+        return Ok(prepared);
+    }
+
+    let mut prepared: PreparedStatement = session.prepare(cql).await?;
+    prepared.set_consistency(Consistency::One);
+    CACHE
+        .lock()
+        .unwrap()
+        .insert(cql.to_string(), prepared.clone());
+    Ok(prepared)
+}
+
+/// Builds a logged batch of the prepared `cqls`, so that either all of them
+/// are applied or none.
+pub async fn prepare_batch(session: &Session, cqls: &[&str]) -> Result<Batch, QueryError> {
+    let mut batch = Batch::default();
+    batch.set_consistency(Consistency::One);
+    for cql in cqls {
+        batch.append_statement(prepare(session, cql).await?);
+    }
+    Ok(batch)
+}
+
+/// Builds a counter batch of the prepared `cqls`, which may only update
+/// counter columns.
+pub async fn prepare_counter_batch(session: &Session, cqls: &[&str]) -> Result<Batch, QueryError> {
+    let mut batch = Batch::new(BatchType::Counter);
+    batch.set_consistency(Consistency::One);
+    for cql in cqls {
+        batch.append_statement(prepare(session, cql).await?);
+    }
+    Ok(batch)
+}
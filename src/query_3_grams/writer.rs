@@ -1,5 +1,7 @@
 use super::specs::ThreeGramGetResult;
+use super::specs::ThreeGramInput;
 use super::specs::ThreeGramInsertResult;
+use crate::stats::snapshot::StatsSnapshot;
 use std::fs::File;
 use std::io::{self, Write};
 
@@ -7,6 +9,45 @@ pub enum WriteOptions {
     FILE(String),
 }
 
+/// Joins `parts` with `-` into a file name below `directory`. Words come from
+/// user input, so anything that could name a file elsewhere (a path
+/// separator, `..` or NUL) is rejected.
+fn result_path(directory: &str, parts: &[&str]) -> Result<String, io::Error> {
+    for part in parts {
+        if part.contains(['/', '\\', '\0']) || part.contains("..") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Cannot use \"{}\" in a result file name",
+                    part.escape_debug()
+                ),
+            ));
+        }
+    }
+    Ok(format!("{}/{}", directory, parts.join("-")))
+}
+
+/// The file the select result of `input` is written to.
+pub fn select_path(input: &ThreeGramInput) -> Result<String, io::Error> {
+    result_path(
+        "/home/projekt/query-results/select",
+        &[&input.word_1, &input.word_2, &input.word_3],
+    )
+}
+
+/// The file the insert result of `input` with its new `freq` is written to.
+pub fn insert_path(input: &ThreeGramInput, freq: i32) -> Result<String, io::Error> {
+    result_path(
+        "/home/projekt/query-results/insert",
+        &[
+            &input.word_1,
+            &input.word_2,
+            &input.word_3,
+            &freq.to_string(),
+        ],
+    )
+}
+
 pub fn write_three_gram(option: WriteOptions, three_gram: &ThreeGramGetResult) -> Result<(), io::Error> {
     match option {
         WriteOptions::FILE(file_name) => {
@@ -22,7 +63,7 @@ pub fn write_stats(option: WriteOptions, stats: &Vec<String>) -> Result<(), io::
         WriteOptions::FILE(file_name) => {
             let mut file = File::create(file_name)?;
             for stat in stats {
-                write!(file, "{}\n", stat)?;
+                writeln!(file, "{}", stat)?;
             }
             Ok(())
         }
@@ -38,3 +79,43 @@ pub fn write_insert(option: WriteOptions, insert: &ThreeGramInsertResult) -> Res
         }
     }
 }
+
+pub fn write_stats_snapshot(option: WriteOptions, snapshot: &StatsSnapshot) -> Result<(), io::Error> {
+    match option {
+        WriteOptions::FILE(file_name) => {
+            let file = File::create(file_name)?;
+            serde_json::to_writer_pretty(file, snapshot)?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(word_1: &str, word_2: &str, word_3: &str) -> ThreeGramInput {
+        ThreeGramInput::new(word_1.into(), word_2.into(), word_3.into())
+    }
+
+    #[test]
+    fn result_paths_are_named_after_the_words() {
+        assert_eq!(
+            select_path(&input("the", "quick", "fox")).unwrap(),
+            "/home/projekt/query-results/select/the-quick-fox"
+        );
+        assert_eq!(
+            insert_path(&input("the", "quick", "fox"), 3).unwrap(),
+            "/home/projekt/query-results/insert/the-quick-fox-3"
+        );
+    }
+
+    #[test]
+    fn result_paths_reject_words_leaving_the_directory() {
+        // `..%2F..%2Ftmp` in a request path arrives here decoded.
+        for word in ["../../tmp", "a/b", "a\\b", "..", "a\0b"] {
+            assert!(select_path(&input("the", word, "fox")).is_err());
+            assert!(insert_path(&input(word, "quick", "fox"), 1).is_err());
+        }
+    }
+}
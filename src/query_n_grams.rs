@@ -0,0 +1,187 @@
+use crate::metrics;
+use crate::normalize;
+use crate::query_3_grams::{self, statements};
+use chrono::Utc;
+use scylla::frame::response::result::CqlValue;
+use scylla::IntoTypedRows;
+use scylla::Session;
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Instant;
+
+pub mod schema;
+pub mod specs;
+
+/// Normalizes every word and checks that the size of the input is configured.
+fn prepare_input(input: &specs::NGramInput) -> Result<specs::NGramInput, Box<dyn Error>> {
+    schema::check(input.n())?;
+    let pipeline = normalize::active();
+    let words: Vec<String> = input
+        .words
+        .iter()
+        .map(|word| pipeline.normalize(word))
+        .collect();
+    if words.iter().any(|word| word.is_empty()) {
+        return Err(format!(
+            "\"{:?}\" is empty after normalization ({})",
+            input,
+            pipeline.describe()
+        )
+        .into());
+    }
+    Ok(specs::NGramInput::new(words))
+}
+
+async fn exact_freq(session: &Session, input: &specs::NGramInput) -> Result<i32, Box<dyn Error>> {
+    let table = schema::exact_table(input.n());
+    let prepared = statements::prepare(session, &table.exact_cql()).await?;
+    let row = session
+        .execute(&prepared, input.words.clone())
+        .await?
+        .maybe_first_row_typed::<(i32,)>()?;
+    Ok(row.map(|(freq,)| freq).unwrap_or(0))
+}
+
+/// Fetches every word filling the free position of `table` given the other
+/// words of `input`.
+pub async fn get_expansion(
+    session: &Session,
+    table: schema::NGramTable,
+    input: &specs::NGramInput,
+) -> Result<specs::Expansion, Box<dyn Error>> {
+    let key: Vec<String> = table
+        .key_positions()
+        .iter()
+        .map(|position| input.words[position - 1].clone())
+        .collect();
+    let start_time = Instant::now();
+    let prepared = statements::prepare(session, &table.select_cql()).await?;
+    let rows = session.execute(&prepared, key).await?.rows;
+    let time_taken = start_time.elapsed();
+    metrics::record_query("get_expansion", &table.name(), time_taken);
+
+    let mut word_map = HashMap::new();
+    if let Some(rows) = rows {
+        for row in rows.into_typed::<(String, i32)>() {
+            let (word, freq) = row?;
+            word_map.insert(word, freq);
+        }
+    }
+    Ok(specs::Expansion {
+        table,
+        word_map,
+        time_taken,
+    })
+}
+
+/// The N-gram counterpart of `query_3_grams::get_3_gram`: the exact frequency
+/// and the expansion of every position.
+pub async fn get(
+    session: &Session,
+    input: &specs::NGramInput,
+) -> Result<specs::NGramGetResult, Box<dyn Error>> {
+    let input = prepare_input(input)?;
+
+    let start_time_one = Instant::now();
+    let exact_freq = exact_freq(session, &input).await?;
+    let time_taken_one = start_time_one.elapsed();
+    metrics::record_query(
+        "get_exact",
+        &schema::exact_table(input.n()).name(),
+        time_taken_one,
+    );
+
+    let start_time_all = Instant::now();
+    let mut expansions = Vec::new();
+    for table in schema::tables(input.n()) {
+        expansions.push(get_expansion(session, table, &input).await?);
+    }
+    let time_taken_all = start_time_all.elapsed();
+
+    Ok(specs::NGramGetResult {
+        input,
+        exact_freq,
+        expansions,
+        time_taken_one,
+        time_taken_all,
+        timestamp: Utc::now(),
+    })
+}
+
+/// Sets the frequency of the N-gram in all of its tables in one logged batch.
+pub async fn write_freq(
+    session: &Session,
+    input: &specs::NGramInput,
+    freq: i32,
+) -> Result<(), Box<dyn Error>> {
+    let cqls: Vec<String> = schema::tables(input.n())
+        .iter()
+        .map(|table| table.insert_cql())
+        .collect();
+    let cqls: Vec<&str> = cqls.iter().map(String::as_str).collect();
+    let batch = statements::prepare_batch(session, &cqls).await?;
+
+    let mut values: Vec<CqlValue> = input.words.iter().cloned().map(CqlValue::Text).collect();
+    values.push(CqlValue::Int(freq));
+    session.batch(&batch, vec![values; cqls.len()]).await?;
+    Ok(())
+}
+
+/// Increments the frequency of the N-gram in all of its tables. 3-grams go
+/// through `query_3_grams::insert`, which also keeps the marginals, the
+/// leaderboard and the time buckets in step with their tables.
+pub async fn insert(
+    session: &Session,
+    input: &specs::NGramInput,
+) -> Result<specs::NGramInsertResult, Box<dyn Error>> {
+    let input = prepare_input(input)?;
+    if input.n() == 3 {
+        let three_gram = query_3_grams::specs::ThreeGramInput::new(
+            input.words[0].clone(),
+            input.words[1].clone(),
+            input.words[2].clone(),
+        );
+        let result = query_3_grams::insert(session, &three_gram).await?;
+        return Ok(specs::NGramInsertResult {
+            input,
+            freq: result.freq,
+            time_taken: result.time_taken,
+            timestamp: result.timestamp,
+        });
+    }
+    let start_time = Instant::now();
+    let freq = exact_freq(session, &input).await? + 1;
+    let result = write_freq(session, &input, freq).await;
+    let time_taken = start_time.elapsed();
+    match result {
+        Ok(()) => metrics::record_query("insert", &format!("{}_grams", input.n()), time_taken),
+        Err(err) => {
+            metrics::record_error("insert");
+            return Err(err);
+        }
+    }
+
+    Ok(specs::NGramInsertResult {
+        input,
+        freq,
+        time_taken,
+        timestamp: Utc::now(),
+    })
+}
+
+/// Inserts every N-gram of every configured size found in `text`, skipping
+/// sizes longer than the text. Returns the number of N-grams inserted.
+pub async fn insert_text(session: &Session, text: &str) -> Result<usize, Box<dyn Error>> {
+    let mut count = 0;
+    for n in schema::configured() {
+        let inputs = match specs::NGramInput::from_text(text, *n) {
+            Ok(inputs) => inputs,
+            Err(_) => continue,
+        };
+        for input in inputs {
+            insert(session, &input).await?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
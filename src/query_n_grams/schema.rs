@@ -0,0 +1,189 @@
+use crate::corpus::{self, Corpus};
+use scylla::Session;
+use std::error::Error;
+use std::sync::OnceLock;
+
+static CONFIGURED: OnceLock<Vec<usize>> = OnceLock::new();
+static NAMES: [&str; 8] = [
+    "two", "three", "four", "five", "six", "seven", "eight", "nine",
+];
+pub static MIN_N: usize = 2;
+pub static MAX_N: usize = 9;
+
+/// One of the `n` tables of the N-gram model. Its partition key holds every
+/// position but `free`, which is the clustering column, so the table answers
+/// "which words fill position `free` given the others". For N=3 these are the
+/// `three_grams_1_2_pk`, `three_grams_1_3_pk` and `three_grams_2_3_pk` tables.
+#[derive(Clone, Copy)]
+pub struct NGramTable {
+    pub n: usize,
+    pub free: usize,
+}
+
+fn columns(positions: &[usize]) -> Vec<String> {
+    positions
+        .iter()
+        .map(|position| format!("word_{}", position))
+        .collect()
+}
+
+impl NGramTable {
+    /// Positions of the partition key, starting at 1.
+    pub fn key_positions(&self) -> Vec<usize> {
+        (1..=self.n)
+            .filter(|position| *position != self.free)
+            .collect()
+    }
+
+    pub fn name(&self) -> String {
+        let positions: Vec<String> = self
+            .key_positions()
+            .iter()
+            .map(|position| position.to_string())
+            .collect();
+        format!("{}_grams_{}_pk", NAMES[self.n - MIN_N], positions.join("_"))
+    }
+
+    pub fn create_cql(&self, corpus: &Corpus) -> String {
+        let all_columns: Vec<String> = columns(&(1..=self.n).collect::<Vec<_>>())
+            .iter()
+            .map(|column| format!("{} text", column))
+            .collect();
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} ({}, freq int, PRIMARY KEY (({}), word_{}))",
+            corpus.table(&self.name()),
+            all_columns.join(", "),
+            columns(&self.key_positions()).join(", "),
+            self.free
+        )
+    }
+
+    /// Selects the free word and frequency of every row with the given key. Like
+    /// the other queries it reads the active corpus.
+    pub fn select_cql(&self) -> String {
+        let conditions: Vec<String> = columns(&self.key_positions())
+            .iter()
+            .map(|column| format!("{} = ?", column))
+            .collect();
+        format!(
+            "SELECT word_{}, freq FROM {} WHERE {}",
+            self.free,
+            corpus::table(&self.name()),
+            conditions.join(" AND ")
+        )
+    }
+
+    /// Selects the frequency of one N-gram, bound in position order.
+    pub fn exact_cql(&self) -> String {
+        let conditions: Vec<String> = columns(&(1..=self.n).collect::<Vec<_>>())
+            .iter()
+            .map(|column| format!("{} = ?", column))
+            .collect();
+        format!(
+            "SELECT freq FROM {} WHERE {}",
+            corpus::table(&self.name()),
+            conditions.join(" AND ")
+        )
+    }
+
+    /// Upserts one N-gram, bound in position order followed by the frequency.
+    pub fn insert_cql(&self) -> String {
+        let all_columns = columns(&(1..=self.n).collect::<Vec<_>>());
+        format!(
+            "INSERT INTO {} ({}, freq) VALUES ({}, ?)",
+            corpus::table(&self.name()),
+            all_columns.join(", "),
+            vec!["?"; self.n].join(", ")
+        )
+    }
+}
+
+/// The tables of the N-gram model for `n`, the one with the last position
+/// free first.
+pub fn tables(n: usize) -> Vec<NGramTable> {
+    (1..=n).rev().map(|free| NGramTable { n, free }).collect()
+}
+
+/// The table exact frequencies are read from: the one keyed by all but the
+/// last word, like `three_grams_1_2_pk`.
+pub fn exact_table(n: usize) -> NGramTable {
+    NGramTable { n, free: n }
+}
+
+fn parse(spec: &str) -> Result<Vec<usize>, String> {
+    let mut sizes = Vec::new();
+    for size in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|size| !size.is_empty())
+    {
+        let size = size
+            .parse::<usize>()
+            .ok()
+            .filter(|size| (MIN_N..=MAX_N).contains(size))
+            .ok_or(format!(
+                "Invalid N-gram size {} (expected {} to {})",
+                size, MIN_N, MAX_N
+            ))?;
+        if !sizes.contains(&size) {
+            sizes.push(size);
+        }
+    }
+    if sizes.is_empty() {
+        return Err(String::from("At least one N-gram size must be configured"));
+    }
+    sizes.sort();
+    Ok(sizes)
+}
+
+/// Sets the process-wide N-gram sizes from `--n-grams <sizes>` or the
+/// `N_GRAMS` environment variable, e.g. `2,3,4`, and returns `args` without
+/// the flag. Defaults to 3-grams only.
+pub fn init(args: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut remaining = Vec::new();
+    let mut spec = std::env::var("N_GRAMS").ok();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--n-grams" {
+            spec = Some(iter.next().ok_or("--n-grams expects a list of sizes")?);
+        } else {
+            remaining.push(arg);
+        }
+    }
+
+    let sizes = parse(spec.as_deref().unwrap_or("3"))?;
+    _ = CONFIGURED.set(sizes);
+    Ok(remaining)
+}
+
+pub fn configured() -> &'static [usize] {
+    CONFIGURED.get_or_init(|| vec![3])
+}
+
+pub fn check(n: usize) -> Result<(), String> {
+    if configured().contains(&n) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{}-grams are not configured (configured: {:?}, see --n-grams)",
+            n,
+            configured()
+        ))
+    }
+}
+
+/// The CQL creating every table of the configured N-gram sizes in `corpus`.
+pub fn create_statements(corpus: &Corpus) -> Vec<String> {
+    configured()
+        .iter()
+        .flat_map(|n| tables(*n))
+        .map(|table| table.create_cql(corpus))
+        .collect()
+}
+
+pub async fn create(session: &Session, corpus: &Corpus) -> Result<(), Box<dyn Error>> {
+    for cql in create_statements(corpus) {
+        session.query(cql, ()).await?;
+    }
+    Ok(())
+}
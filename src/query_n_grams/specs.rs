@@ -0,0 +1,168 @@
+use super::schema::NGramTable;
+use crate::tokenizer;
+use chrono::{DateTime, SecondsFormat, Utc};
+use core::fmt::{self, Write};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::time::Duration;
+
+static DEFAULT_NUMBER_OF_N_GRAMS_TO_PRINT: usize = 10;
+
+#[derive(Clone)]
+pub struct NGramInput {
+    pub words: Vec<String>,
+}
+
+/// The words filling the free position of `table` given the other words of
+/// the input.
+pub struct Expansion {
+    pub table: NGramTable,
+    pub word_map: HashMap<String, i32>,
+    pub time_taken: Duration,
+}
+
+pub struct NGramGetResult {
+    pub input: NGramInput,
+    pub exact_freq: i32,
+    pub expansions: Vec<Expansion>,
+    pub time_taken_one: Duration,
+    pub time_taken_all: Duration,
+    pub timestamp: DateTime<Utc>,
+}
+
+pub struct NGramInsertResult {
+    pub input: NGramInput,
+    pub freq: i32,
+    pub time_taken: Duration,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl NGramInput {
+    pub fn new(words: Vec<String>) -> NGramInput {
+        NGramInput { words }
+    }
+
+    pub fn n(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Tokenizes `input` and returns every N-gram of consecutive words in it.
+    pub fn from_text(input: &str, n: usize) -> Result<Vec<NGramInput>, String> {
+        let words = tokenizer::active().tokenize(input);
+
+        if words.len() < n {
+            return Err(format!("Input must contain at least {} words", n));
+        }
+
+        Ok(words
+            .windows(n)
+            .map(|window| NGramInput::new(window.to_vec()))
+            .collect())
+    }
+}
+
+impl fmt::Debug for NGramInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.words.join(" "))
+    }
+}
+
+impl fmt::Debug for NGramInsertResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut result_string = String::new();
+        writeln!(
+            &mut result_string,
+            "Inserted {}-gram: {:?} = {} in {}.{:03} seconds",
+            self.input.n(),
+            self.input,
+            self.freq,
+            self.time_taken.as_secs(),
+            self.time_taken.subsec_millis()
+        )?;
+        writeln!(
+            &mut result_string,
+            "Timestamp: {}",
+            self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+        )?;
+        write!(f, "{}", result_string)
+    }
+}
+
+impl fmt::Debug for NGramGetResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut result_string = String::new();
+        writeln!(
+            &mut result_string,
+            "Given {}-gram: {:?} = {}",
+            self.input.n(),
+            self.input,
+            self.exact_freq
+        )?;
+        writeln!(
+            &mut result_string,
+            "Timestamp: {}",
+            self.timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+        )?;
+        writeln!(
+            &mut result_string,
+            "Time taken to get the exact frequency: {}.{:03} seconds",
+            self.time_taken_one.as_secs(),
+            self.time_taken_one.subsec_millis()
+        )?;
+        writeln!(
+            &mut result_string,
+            "Time taken to get all values: {}.{:03} seconds",
+            self.time_taken_all.as_secs(),
+            self.time_taken_all.subsec_millis()
+        )?;
+        for expansion in &self.expansions {
+            let positions = expansion
+                .table
+                .key_positions()
+                .iter()
+                .map(|position| position.to_string())
+                .join(", ");
+            let words = self
+                .input
+                .words
+                .iter()
+                .enumerate()
+                .map(|(index, word)| {
+                    if index + 1 == expansion.table.free {
+                        "_____"
+                    } else {
+                        word.as_str()
+                    }
+                })
+                .join(" ");
+            writeln!(
+                &mut result_string,
+                "--- query executed based on words {} ---",
+                positions
+            )?;
+            writeln!(&mut result_string, "words: {}", words)?;
+            writeln!(
+                &mut result_string,
+                "Time taken for query: {}.{:03} seconds ({} rows)",
+                expansion.time_taken.as_secs(),
+                expansion.time_taken.subsec_millis(),
+                expansion.word_map.len()
+            )?;
+            let count = expansion.word_map.len();
+            let top_elements: Vec<_> = expansion
+                .word_map
+                .iter()
+                .sorted_by(|a, b| b.1.cmp(a.1))
+                .take(DEFAULT_NUMBER_OF_N_GRAMS_TO_PRINT)
+                .collect();
+            for (word, frequency) in top_elements {
+                writeln!(&mut result_string, " {}: {}", word, frequency)?;
+            }
+            if count > DEFAULT_NUMBER_OF_N_GRAMS_TO_PRINT {
+                let remaining_count = count - DEFAULT_NUMBER_OF_N_GRAMS_TO_PRINT;
+                writeln!(&mut result_string, " ... and {} more", remaining_count)?;
+            }
+        }
+        write!(f, "{}", result_string)
+    }
+}
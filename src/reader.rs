@@ -1,38 +1,512 @@
 use crate::query_3_grams::specs;
+use crate::tokenizer;
+use core::fmt;
+use serde::Deserialize;
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
 
-fn process_line(line: String, vec: &mut Vec<specs::ThreeGramInput>) -> Result<(), Box<dyn Error>> {
-    let words: Vec<&str> = line.trim().split_whitespace().collect();
+static WORD_COLUMNS: [&str; 3] = ["word_1", "word_2", "word_3"];
+static COUNT_COLUMNS: [&str; 2] = ["count", "freq"];
 
-    let word_1 = String::from(words[0]);
-    let word_2 = String::from(words[1]);
-    let word_3 = String::from(words[2]);
+/// How invalid lines are handled: `Strict` stops at the first one, `Lenient`
+/// skips them and collects their errors.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Strict,
+    Lenient,
+}
 
-    let three_gram = specs::ThreeGramInput::new(word_1, word_2, word_3);
+/// Layout of a bulk input file. `Text` lines are tokenized into 3-grams,
+/// `Csv` and `Tsv` files need a header with `word_1`, `word_2` and `word_3`
+/// columns, and `Jsonl` lines are objects with the same fields. The last three
+/// may carry a `count` (or `freq`) that defaults to 1.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Csv,
+    Tsv,
+    Jsonl,
+}
 
-    vec.push(three_gram);
+pub struct LineError {
+    pub line_number: usize,
+    pub message: String,
+}
 
-    Ok(())
+#[derive(Deserialize)]
+struct JsonRecord {
+    word_1: String,
+    word_2: String,
+    word_3: String,
+    #[serde(alias = "freq")]
+    count: Option<i32>,
 }
 
-pub fn read(path: String) -> Result<Vec<specs::ThreeGramInput>, Box<dyn Error>> {
-    let mut three_gram_vec: Vec<specs::ThreeGramInput> = Vec::new();
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+/// Positions of the used columns in a CSV/TSV header.
+struct Columns {
+    words: [usize; 3],
+    count: Option<usize>,
+}
 
-    for line in reader.lines() {
-        match line {
-            Ok(line) => {
-                if let Err(err) = process_line(line, &mut three_gram_vec) {
-                    return Err(err);
-                }
+enum Source {
+    Lines(io::Lines<Box<dyn BufRead + Send>>, Format),
+    Delimited(csv::StringRecordsIntoIter<Box<dyn BufRead + Send>>, Columns),
+}
+
+/// Lazily reads 3-grams and their counts from a bulk input file. Blank lines
+/// are skipped, and so are lines starting with `#` when comments are enabled;
+/// they are off by default because `#` is a valid start of a word. A text line
+/// with more than three words yields every 3-gram of consecutive words in it.
+pub struct ThreeGramReader {
+    source: Source,
+    comments: bool,
+    line_number: usize,
+    pending: VecDeque<specs::ThreeGram>,
+    mode: Mode,
+    errors: Vec<LineError>,
+    done: bool,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Format, String> {
+        match name {
+            "text" => Ok(Format::Text),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "jsonl" => Ok(Format::Jsonl),
+            _ => Err(format!("Unknown input format: {}", name)),
+        }
+    }
+
+    /// Picks the format from the file extension, defaulting to `Text`.
+    pub fn detect(path: &str) -> Format {
+        match Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("csv") => Format::Csv,
+            Some("tsv") | Some("tab") => Format::Tsv,
+            Some("jsonl") | Some("ndjson") => Format::Jsonl,
+            _ => Format::Text,
+        }
+    }
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line_number, self.message)
+    }
+}
+
+impl fmt::Debug for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl Error for LineError {}
+
+fn parse_count(count: &str) -> Result<i32, String> {
+    match count.trim().parse::<i32>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("invalid count \"{}\"", count)),
+    }
+}
+
+fn three_gram(words: [String; 3], count: i32) -> Result<specs::ThreeGram, String> {
+    for (name, word) in WORD_COLUMNS.iter().zip(&words) {
+        if word.trim().is_empty() {
+            return Err(format!("missing {}", name));
+        }
+    }
+    let [word_1, word_2, word_3] = words.map(|word| word.trim().to_string());
+    Ok(specs::ThreeGram::new(word_1, word_2, word_3, count))
+}
+
+fn process_text(line: &str) -> Result<Vec<specs::ThreeGram>, String> {
+    let words = tokenizer::active().tokenize(line);
+
+    if words.len() < 3 {
+        return Err(format!("expected at least 3 words, found {}", words.len()));
+    }
+
+    Ok(specs::ThreeGramInput::from_words(&words)
+        .into_iter()
+        .map(|input| specs::ThreeGram::new(input.word_1, input.word_2, input.word_3, 1))
+        .collect())
+}
+
+fn process_json(line: &str) -> Result<Vec<specs::ThreeGram>, String> {
+    let record: JsonRecord = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let count = match record.count {
+        Some(count) if count <= 0 => return Err(format!("invalid count \"{}\"", count)),
+        Some(count) => count,
+        None => 1,
+    };
+    let three_gram = three_gram([record.word_1, record.word_2, record.word_3], count)?;
+    Ok(vec![three_gram])
+}
+
+fn process_record(
+    record: &csv::StringRecord,
+    columns: &Columns,
+) -> Result<specs::ThreeGram, String> {
+    let words = columns
+        .words
+        .map(|index| record.get(index).unwrap_or("").to_string());
+    let count = match columns.count.and_then(|index| record.get(index)) {
+        Some(count) if !count.trim().is_empty() => parse_count(count)?,
+        _ => 1,
+    };
+    three_gram(words, count)
+}
+
+fn find_columns(headers: &csv::StringRecord) -> Result<Columns, String> {
+    let position = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.trim().eq_ignore_ascii_case(name))
+    };
+    let mut words = [0; 3];
+    for (index, name) in WORD_COLUMNS.iter().enumerate() {
+        words[index] = position(name).ok_or(format!("Header has no {} column", name))?;
+    }
+    let count = COUNT_COLUMNS.iter().find_map(|name| position(name));
+    Ok(Columns { words, count })
+}
+
+impl ThreeGramReader {
+    /// Reads `input` as `format`, skipping lines starting with `#` if
+    /// `comments` is set.
+    pub fn new(
+        input: Box<dyn BufRead + Send>,
+        format: Format,
+        mode: Mode,
+        comments: bool,
+    ) -> Result<ThreeGramReader, Box<dyn Error>> {
+        let source = match format {
+            Format::Text | Format::Jsonl => Source::Lines(input.lines(), format),
+            Format::Csv | Format::Tsv => {
+                let mut reader = csv::ReaderBuilder::new()
+                    .delimiter(if format == Format::Tsv { b'\t' } else { b',' })
+                    .quoting(format == Format::Csv)
+                    .comment(comments.then_some(b'#'))
+                    .flexible(true)
+                    .from_reader(input);
+                let columns = find_columns(reader.headers()?)?;
+                Source::Delimited(reader.into_records(), columns)
             }
-            Err(err) => return Err(Box::new(err)),
         };
+        Ok(ThreeGramReader {
+            source,
+            comments,
+            line_number: 0,
+            pending: VecDeque::new(),
+            mode,
+            errors: Vec::new(),
+            done: false,
+        })
+    }
+
+    /// Errors of the lines skipped so far in lenient mode.
+    pub fn errors(&self) -> &[LineError] {
+        &self.errors
+    }
+
+    fn fail(&mut self, message: String) -> Option<Result<specs::ThreeGram, LineError>> {
+        let error = LineError {
+            line_number: self.line_number,
+            message,
+        };
+        if self.mode == Mode::Strict {
+            self.done = true;
+            return Some(Err(error));
+        }
+        self.errors.push(error);
+        None
     }
 
-    Ok(three_gram_vec)
+    /// Reads the next non-empty record. I/O errors other than invalid UTF-8
+    /// end the input, as the underlying reader is unusable after them.
+    fn read_record(&mut self) -> Option<Result<Vec<specs::ThreeGram>, String>> {
+        match &mut self.source {
+            Source::Lines(lines, format) => loop {
+                let line = lines.next()?;
+                self.line_number += 1;
+                let line = match line {
+                    Ok(line) => line,
+                    Err(err) => {
+                        self.done = err.kind() != io::ErrorKind::InvalidData;
+                        return Some(Err(err.to_string()));
+                    }
+                };
+                let trimmed = line.trim();
+                if trimmed.is_empty() || (self.comments && trimmed.starts_with('#')) {
+                    continue;
+                }
+                return Some(match format {
+                    Format::Jsonl => process_json(trimmed),
+                    _ => process_text(trimmed),
+                });
+            },
+            Source::Delimited(records, columns) => match records.next()? {
+                Ok(record) => {
+                    if let Some(position) = record.position() {
+                        self.line_number = position.line() as usize;
+                    }
+                    Some(process_record(&record, columns).map(|three_gram| vec![three_gram]))
+                }
+                Err(err) => {
+                    if let Some(position) = err.position() {
+                        self.line_number = position.line() as usize;
+                    }
+                    self.done = err.is_io_error();
+                    Some(Err(err.to_string()))
+                }
+            },
+        }
+    }
 }
 
+impl Iterator for ThreeGramReader {
+    type Item = Result<specs::ThreeGram, LineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(three_gram) = self.pending.pop_front() {
+                return Some(Ok(three_gram));
+            }
+            if self.done {
+                return None;
+            }
+
+            match self.read_record() {
+                Some(Ok(three_grams)) => self.pending.extend(three_grams),
+                Some(Err(message)) => {
+                    if let Some(error) = self.fail(message) {
+                        return Some(error);
+                    }
+                }
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Opens a bulk input file, `-` meaning stdin. Without an explicit `format`
+/// it is detected from the file extension.
+pub fn open(
+    path: &str,
+    format: Option<Format>,
+    mode: Mode,
+    comments: bool,
+) -> Result<ThreeGramReader, Box<dyn Error>> {
+    let input: Box<dyn BufRead + Send> = if path == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        let file = File::open(path).map_err(|err| format!("Could not open {}: {}", path, err))?;
+        Box::new(BufReader::new(file))
+    };
+    ThreeGramReader::new(
+        input,
+        format.unwrap_or_else(|| Format::detect(path)),
+        mode,
+        comments,
+    )
+}
+
+/// Prints the lines that were skipped in lenient mode.
+pub fn print_skipped(errors: &[LineError]) {
+    if errors.is_empty() {
+        return;
+    }
+    println!("Skipped {} invalid lines:", errors.len());
+    for error in errors {
+        println!("{}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn reader(input: &str, format: Format, mode: Mode, comments: bool) -> ThreeGramReader {
+        let input: Box<dyn BufRead + Send> = Box::new(Cursor::new(input.to_string()));
+        ThreeGramReader::new(input, format, mode, comments).unwrap()
+    }
+
+    fn tuples(three_grams: Vec<specs::ThreeGram>) -> Vec<(String, String, String, i32)> {
+        three_grams
+            .into_iter()
+            .map(|three_gram| {
+                (
+                    three_gram.word_1,
+                    three_gram.word_2,
+                    three_gram.word_3,
+                    three_gram.freq,
+                )
+            })
+            .collect()
+    }
+
+    fn read_all(input: &str, format: Format) -> Vec<(String, String, String, i32)> {
+        let three_grams = reader(input, format, Mode::Strict, false)
+            .collect::<Result<Vec<_>, LineError>>()
+            .unwrap();
+        tuples(three_grams)
+    }
+
+    fn tuple(word_1: &str, word_2: &str, word_3: &str, freq: i32) -> (String, String, String, i32) {
+        (word_1.into(), word_2.into(), word_3.into(), freq)
+    }
+
+    #[test]
+    fn text_lines_yield_every_consecutive_3_gram() {
+        let input = "\nthe quick brown fox\n\n";
+        assert_eq!(
+            read_all(input, Format::Text),
+            vec![
+                tuple("the", "quick", "brown", 1),
+                tuple("quick", "brown", "fox", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_mode_stops_at_the_first_short_line() {
+        let input = "a b c\n\na b\nd e f\n";
+        let results: Vec<_> = reader(input, Format::Text, Mode::Strict, false).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        let error = results[1].as_ref().err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 3: expected at least 3 words, found 2"
+        );
+    }
+
+    #[test]
+    fn lenient_mode_skips_short_lines_and_collects_their_errors() {
+        let mut reader = reader("a b\na b c\nd\n", Format::Text, Mode::Lenient, false);
+        let three_grams = reader
+            .by_ref()
+            .collect::<Result<Vec<_>, LineError>>()
+            .unwrap();
+        assert_eq!(tuples(three_grams), vec![tuple("a", "b", "c", 1)]);
+        let lines: Vec<usize> = reader
+            .errors()
+            .iter()
+            .map(|error| error.line_number)
+            .collect();
+        assert_eq!(lines, vec![1, 3]);
+    }
+
+    #[test]
+    fn format_is_detected_from_the_extension() {
+        assert!(Format::detect("grams.csv") == Format::Csv);
+        assert!(Format::detect("grams.tab") == Format::Tsv);
+        assert!(Format::detect("grams.ndjson") == Format::Jsonl);
+        assert!(Format::detect("grams.txt") == Format::Text);
+        assert!(Format::parse("xml").is_err());
+    }
+
+    #[test]
+    fn csv_reads_columns_by_header_name() {
+        let input = "count,word_3,word_2,word_1\n2,c,b,a\n,\"f, g\",e,d\n";
+        assert_eq!(
+            read_all(input, Format::Csv),
+            vec![tuple("a", "b", "c", 2), tuple("d", "e", "f, g", 1)]
+        );
+    }
+
+    #[test]
+    fn csv_needs_the_word_columns() {
+        let input: Box<dyn BufRead + Send> = Box::new(Cursor::new("word_1,word_2\na,b\n"));
+        assert!(ThreeGramReader::new(input, Format::Csv, Mode::Strict, false).is_err());
+    }
+
+    #[test]
+    fn tsv_accepts_freq_and_does_not_quote() {
+        let input = "word_1\tword_2\tword_3\tfreq\n\"a\tb\tc\t3\n";
+        assert_eq!(
+            read_all(input, Format::Tsv),
+            vec![tuple("\"a", "b", "c", 3)]
+        );
+    }
+
+    #[test]
+    fn jsonl_reads_objects_with_an_optional_count() {
+        let input = "{\"word_1\": \"a\", \"word_2\": \"b\", \"word_3\": \"c\"}\n\
+                     {\"word_1\": \"d\", \"word_2\": \"e\", \"word_3\": \"f\", \"freq\": 4}\n";
+        assert_eq!(
+            read_all(input, Format::Jsonl),
+            vec![tuple("a", "b", "c", 1), tuple("d", "e", "f", 4)]
+        );
+    }
+
+    #[test]
+    fn words_may_start_with_a_hash_unless_comments_are_enabled() {
+        let csv = "word_1,word_2,word_3,count\n####,was,great,5\n# not a 3-gram\n";
+        let text = "#1 in the charts\n";
+        let jsonl = "{\"word_1\": \"#a\", \"word_2\": \"b\", \"word_3\": \"c\"}\n";
+
+        let results: Vec<_> = reader(csv, Format::Csv, Mode::Strict, false).collect();
+        assert_eq!(
+            tuples(vec![results[0].as_ref().ok().unwrap().clone()]),
+            vec![tuple("####", "was", "great", 5)]
+        );
+        assert!(results[1].is_err());
+        assert_eq!(read_all(text, Format::Text)[0], tuple("#1", "in", "the", 1));
+        assert_eq!(
+            read_all(jsonl, Format::Jsonl),
+            vec![tuple("#a", "b", "c", 1)]
+        );
+
+        for (input, format) in [(csv, Format::Csv), (text, Format::Text)] {
+            let results: Vec<_> = reader(input, format, Mode::Strict, true).collect();
+            assert!(results.is_empty());
+        }
+    }
+
+    #[test]
+    fn strict_mode_stops_at_the_first_invalid_record() {
+        let input = "word_1,word_2,word_3,count\na,b,c,1\nd,,f,1\ng,h,i,0\nj,k,l,1\n";
+        let results: Vec<_> = reader(input, Format::Csv, Mode::Strict, false).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        let error = results[1].as_ref().err().unwrap();
+        assert_eq!(error.line_number, 3);
+        assert_eq!(error.message, "missing word_2");
+    }
+
+    #[test]
+    fn lenient_mode_skips_invalid_records_and_collects_their_errors() {
+        let input = "{\"word_1\": \"a\", \"word_2\": \"b\", \"word_3\": \"c\"}\n\
+                     not json\n\
+                     {\"word_1\": \"d\", \"word_2\": \"e\", \"word_3\": \"f\", \"count\": -1}\n\
+                     {\"word_1\": \"g\", \"word_2\": \"h\", \"word_3\": \"i\", \"count\": 2}\n";
+        let mut reader = reader(input, Format::Jsonl, Mode::Lenient, false);
+        let three_grams = reader
+            .by_ref()
+            .collect::<Result<Vec<_>, LineError>>()
+            .unwrap();
+        assert_eq!(
+            tuples(three_grams),
+            vec![tuple("a", "b", "c", 1), tuple("g", "h", "i", 2)]
+        );
+        let lines: Vec<usize> = reader
+            .errors()
+            .iter()
+            .map(|error| error.line_number)
+            .collect();
+        assert_eq!(lines, vec![2, 3]);
+        assert_eq!(reader.errors()[1].message, "invalid count \"-1\"");
+    }
+}
@@ -0,0 +1,106 @@
+use crate::metrics;
+use crate::normalize;
+use crate::query_3_grams::{self, specs};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use scylla::Session;
+use serde::Serialize;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+type ApiError = (StatusCode, String);
+
+#[derive(Serialize)]
+struct BulkGetResult {
+    run_id: String,
+    results: Vec<specs::ThreeGramGetResult>,
+}
+
+fn internal_error(err: Box<dyn Error>) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+async fn get_three_gram(
+    State(session): State<Arc<Session>>,
+    Path((word_1, word_2, word_3)): Path<(String, String, String)>,
+) -> Result<Json<specs::ThreeGramGetResult>, ApiError> {
+    let input = specs::ThreeGramInput::new(word_1, word_2, word_3);
+    let result = query_3_grams::get_3_gram(&session, &input, None)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(result))
+}
+
+async fn get_pair(
+    State(session): State<Arc<Session>>,
+    Path((positions, first, second)): Path<(String, String, String)>,
+) -> Result<Json<specs::QueryResult>, ApiError> {
+    let pair_table = match positions.as_str() {
+        "1-2" => specs::PairTable::FirstSecond,
+        "1-3" => specs::PairTable::FirstThird,
+        "2-3" => specs::PairTable::SecondThird,
+        _ => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!(
+                    "Unknown word positions {}, expected 1-2, 1-3 or 2-3",
+                    positions
+                ),
+            ))
+        }
+    };
+    let word_pair = normalize::active().normalize_pair(&specs::WordPair::new(first, second));
+    let result = query_3_grams::get_pair(&session, pair_table, word_pair)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(result))
+}
+
+async fn insert_three_gram(
+    State(session): State<Arc<Session>>,
+    Json(input): Json<specs::ThreeGramInput>,
+) -> Result<Json<specs::ThreeGramInsertResult>, ApiError> {
+    let result = query_3_grams::insert(&session, &input)
+        .await
+        .map_err(internal_error)?;
+    Ok(Json(result))
+}
+
+async fn bulk_get(
+    State(session): State<Arc<Session>>,
+    Json(inputs): Json<Vec<specs::ThreeGramInput>>,
+) -> Result<Json<BulkGetResult>, ApiError> {
+    let run_id = Uuid::new_v4().to_string();
+    let mut results = Vec::new();
+    for input in &inputs {
+        let result = query_3_grams::get_3_gram(&session, input, Some(&run_id))
+            .await
+            .map_err(internal_error)?;
+        results.push(result);
+    }
+    Ok(Json(BulkGetResult { run_id, results }))
+}
+
+pub fn router(session: Arc<Session>) -> Router {
+    Router::new()
+        .route("/three-grams", post(insert_three_gram))
+        .route("/three-grams/bulk", post(bulk_get))
+        .route("/three-grams/:word_1/:word_2/:word_3", get(get_three_gram))
+        .route("/pairs/:positions/:first/:second", get(get_pair))
+        .with_state(session)
+        .merge(metrics::router())
+}
+
+/// Serves the HTTP/JSON API on `addr`, sharing `session` between requests.
+pub async fn serve(session: Session, addr: SocketAddr) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let app = router(Arc::new(session));
+    println!("Serving the 3-gram API on http://{}", addr);
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
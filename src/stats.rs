@@ -6,14 +6,115 @@ use std::error::Error;
 use std::fs;
 use std::io::{self, BufRead};
 
+pub mod diff;
+pub mod filter;
 pub mod helpers;
+pub mod prometheus;
+pub mod snapshot;
+pub mod summary;
+pub mod timeseries;
 
-pub fn create_select_stats() -> Result<(), Box<dyn Error>> {
-    let mut total_exact_frequency_time = 0.0;
-    let mut total_all_values_time = 0.0;
-    let mut exact_frequency_times = Vec::new();
-    let mut all_values_times = Vec::new();
-    let mut count = 0;
+static PAIR_QUERY_SECTIONS: [(&str, &str); 3] = [
+    (
+        "--- query executed based on first and second word ---",
+        "three_grams_1_2_pk",
+    ),
+    (
+        "--- query executed based on first and third word ---",
+        "three_grams_1_3_pk",
+    ),
+    (
+        "--- query executed based on second and third word ---",
+        "three_grams_2_3_pk",
+    ),
+];
+
+#[derive(Default)]
+struct SelectRecord {
+    three_gram: String,
+    timestamp: Option<DateTime<Utc>>,
+    run_id: Option<String>,
+    exact_frequency_time: Option<f64>,
+    all_values_time: Option<f64>,
+    pair_queries: [Option<(f64, f64)>; 3],
+    complete: bool,
+}
+
+/// Latency samples of one metric, with the partition sizes of pair queries
+/// and the timestamps used for the time series where they are known.
+pub struct MetricSamples {
+    pub name: String,
+    pub label: String,
+    pub times: Vec<f64>,
+    pub row_counts: Vec<f64>,
+    pub series: Vec<(DateTime<Utc>, f64)>,
+}
+
+/// All metrics collected for one kind of operation, e.g. select or insert.
+pub struct OperationSamples {
+    pub operation: String,
+    pub metrics: Vec<MetricSamples>,
+}
+
+impl MetricSamples {
+    pub fn new(name: &str, label: &str) -> MetricSamples {
+        MetricSamples {
+            name: name.to_string(),
+            label: label.to_string(),
+            times: Vec::new(),
+            row_counts: Vec::new(),
+            series: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, time: f64, timestamp: Option<DateTime<Utc>>) {
+        self.times.push(time);
+        if let Some(timestamp) = timestamp {
+            self.series.push((timestamp, time));
+        }
+    }
+
+    fn write_lines(
+        &self,
+        summary: &summary::LatencySummary,
+        output: &mut Vec<String>,
+    ) -> Result<(), core::fmt::Error> {
+        let mut tmp_string = String::new();
+        write!(&mut tmp_string, "--- {} ---", self.label)?;
+        output.push(tmp_string.clone());
+        summary.write_lines(&self.label, output)?;
+        if summary.is_empty() || self.row_counts.is_empty() {
+            return Ok(());
+        }
+        tmp_string.clear();
+        write!(
+            &mut tmp_string,
+            "Average Rows for {}: {:.1}",
+            self.label,
+            helpers::calculate_mean(&self.row_counts)
+        )?;
+        output.push(tmp_string.clone());
+        tmp_string.clear();
+        write!(
+            &mut tmp_string,
+            "Correlation between Partition Size and Latency for {}: {:.3}",
+            self.label,
+            helpers::calculate_correlation(&self.row_counts, &self.times)
+        )?;
+        output.push(tmp_string.clone());
+        Ok(())
+    }
+}
+
+pub fn read_select_samples(
+    filter: &filter::StatsFilter,
+) -> Result<OperationSamples, Box<dyn Error>> {
+    let mut exact_frequency = MetricSamples::new("exact_frequency", "Exact Frequency");
+    let mut all_values = MetricSamples::new("all_values", "All Values");
+    let mut pair_queries: Vec<MetricSamples> = PAIR_QUERY_SECTIONS
+        .iter()
+        .map(|(_, table)| MetricSamples::new(table, table))
+        .collect();
 
     for entry in glob("/home/projekt/query-results/select/*").expect("Failed to read glob pattern")
     {
@@ -21,149 +122,74 @@ pub fn create_select_stats() -> Result<(), Box<dyn Error>> {
             Ok(path) => {
                 let file = fs::File::open(&path)?;
                 let reader = io::BufReader::new(file);
+                let mut section: Option<usize> = None;
+                let mut record = SelectRecord::default();
 
                 for line in reader.lines() {
                     let line = line?;
-                    if line.starts_with("Time taken to get the exact frequency:") {
-                        if let Some(time) = helpers::parse_time_from_line_select(&line) {
-                            total_exact_frequency_time += time;
-                            exact_frequency_times.push(time);
+                    if let Some(index) = PAIR_QUERY_SECTIONS
+                        .iter()
+                        .position(|(header, _)| line == *header)
+                    {
+                        section = Some(index);
+                    } else if line.starts_with("Given 3-gram:") {
+                        record.three_gram =
+                            helpers::parse_three_gram_from_line(&line).unwrap_or_default();
+                    } else if line.starts_with("Timestamp:") {
+                        record.timestamp = helpers::parse_timestamp_from_line(&line);
+                    } else if let Some(run_id) = line.strip_prefix("Run ID: ") {
+                        record.run_id = Some(run_id.trim().to_string());
+                    } else if line.starts_with("Time taken for pair query:") {
+                        if let (Some(index), Some(time), Some(rows)) = (
+                            section,
+                            helpers::parse_time_from_line_select(&line),
+                            helpers::parse_row_count_from_line(&line),
+                        ) {
+                            record.pair_queries[index] = Some((time, rows));
                         }
+                    } else if line.starts_with("Time taken to get the exact frequency:") {
+                        record.exact_frequency_time = helpers::parse_time_from_line_select(&line);
                     } else if line.starts_with("Time taken to get all values:") {
-                        if let Some(time) = helpers::parse_time_from_line_select(&line) {
-                            total_all_values_time += time;
-                            all_values_times.push(time);
-                        }
-                        count += 1;
-                        break;
+                        record.all_values_time = helpers::parse_time_from_line_select(&line);
+                        record.complete = true;
+                    }
+                }
+
+                let timestamp = record.timestamp.or_else(|| helpers::modified_time(&path));
+                if !record.complete
+                    || !filter.matches(timestamp, &record.three_gram, record.run_id.as_deref())
+                {
+                    continue;
+                }
+                if let Some(time) = record.exact_frequency_time {
+                    exact_frequency.push(time, timestamp);
+                }
+                if let Some(time) = record.all_values_time {
+                    all_values.push(time, timestamp);
+                }
+                for (index, pair_query) in record.pair_queries.iter().enumerate() {
+                    if let Some((time, rows)) = pair_query {
+                        pair_queries[index].times.push(*time);
+                        pair_queries[index].row_counts.push(*rows);
                     }
                 }
             }
             Err(e) => println!("Error reading file: {:?}", e),
         }
     }
-    if count == 0 {
-        return Ok(());
-    }
-
-    let average_exact_frequency_time = total_exact_frequency_time / count as f64;
-    let average_all_values_time = total_all_values_time / count as f64;
-
-    let std_dev_exact_frequency =
-        helpers::calculate_std_dev(&exact_frequency_times, average_exact_frequency_time);
-    let std_dev_all_values = helpers::calculate_std_dev(&all_values_times, average_all_values_time);
-
-    let median_exact_frequency_time = helpers::calculate_median(&mut exact_frequency_times);
-    let median_all_values_time = helpers::calculate_median(&mut all_values_times);
-
-    let (min_exact_frequency_time, max_exact_frequency_time) =
-        helpers::calculate_min_max(&exact_frequency_times);
-    let (min_all_values_time, max_all_values_time) = helpers::calculate_min_max(&all_values_times);
-
-    let percentile_90_exact = helpers::calculate_percentile(&exact_frequency_times, 90);
-    let percentile_90_all_values = helpers::calculate_percentile(&all_values_times, 90);
-
-    let throughput_exact_frequency = count as f64 / total_exact_frequency_time;
-    let throughput_all_values = count as f64 / total_all_values_time;
-
-    let utc: DateTime<Utc> = Utc::now();
-    let formated_date_time = utc.format("%Y-%m-%dT%H:%M:%S").to_string();
-    let output_file_path = String::from("/home/projekt/stats/select/") + &formated_date_time;
-
-    let mut output: Vec<String> = Vec::new();
-
-    let mut tmp_string = String::new();
-    write!(
-        &mut tmp_string,
-        "Average Time Taken for Exact Frequency: {:.3} seconds (Std Dev: {:.3})",
-        average_exact_frequency_time, std_dev_exact_frequency
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "Average Time Taken for All Values: {:.3} seconds (Std Dev: {:.3})",
-        average_all_values_time, std_dev_all_values
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "Median Time Taken for Exact Frequency: {:.3} seconds",
-        median_exact_frequency_time
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "Median Time Taken for All Values: {:.3} seconds",
-        median_all_values_time
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "Min/Max Time Taken for Exact Frequency: {:.3}/{:.3} seconds",
-        min_exact_frequency_time, max_exact_frequency_time
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "Min/Max Time Taken for All Values: {:.3}/{:.3} seconds",
-        min_all_values_time, max_all_values_time
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "90th Percentile Time for Exact Frequency: {:.3} seconds",
-        percentile_90_exact
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "90th Percentile Time for All Values: {:.3} seconds",
-        percentile_90_all_values
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "Throughput for Exact Frequency: {:.3} queries/second",
-        throughput_exact_frequency
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "Throughput for All Values: {:.3} queries/second",
-        throughput_all_values
-    )?;
-    output.push(tmp_string.clone());
-
-    let option = writer::WriteOptions::FILE(output_file_path.to_string());
-    let result = writer::write_stats(option, &output);
 
-    if let Err(e) = result {
-        println!("Error writing to file: {:?}", e);
-        return Ok(());
-    }
-    println!("Statistics for SELECT queries: ");
-    println!("");
-    for line in output {
-        println!("{}", line);
-    }
-    println!("\nThis information can also be found in file:");
-    println!("{}", output_file_path);
-    Ok(())
+    let mut metrics = vec![exact_frequency, all_values];
+    metrics.extend(pair_queries);
+    Ok(OperationSamples {
+        operation: String::from("select"),
+        metrics,
+    })
 }
 
-pub fn create_insert_stats() -> Result<(), Box<dyn Error>> {
-    let mut total_exact_frequency_time = 0.0;
-    let mut exact_frequency_times = Vec::new();
-    let mut count = 0;
+pub fn read_insert_samples(
+    filter: &filter::StatsFilter,
+) -> Result<OperationSamples, Box<dyn Error>> {
+    let mut exact_frequency = MetricSamples::new("exact_frequency", "Exact Frequency");
 
     for entry in glob("/home/projekt/query-results/insert/*").expect("Failed to read glob pattern")
     {
@@ -171,77 +197,75 @@ pub fn create_insert_stats() -> Result<(), Box<dyn Error>> {
             Ok(path) => {
                 let file = fs::File::open(&path)?;
                 let reader = io::BufReader::new(file);
+                let mut time: Option<f64> = None;
+                let mut three_gram = String::new();
+                let mut timestamp: Option<DateTime<Utc>> = None;
+                let mut run_id: Option<String> = None;
 
                 for line in reader.lines() {
                     let line = line?;
                     if line.starts_with("Inserted 3-gram:") {
-                        if let Some(time) = helpers::parse_time_from_line_insert(&line) {
-                            total_exact_frequency_time += time;
-                            exact_frequency_times.push(time);
-                            count += 1;
-                        }
+                        time = helpers::parse_time_from_line_insert(&line);
+                        three_gram = helpers::parse_three_gram_from_line(&line).unwrap_or_default();
+                    } else if line.starts_with("Timestamp:") {
+                        timestamp = helpers::parse_timestamp_from_line(&line);
+                    } else if let Some(id) = line.strip_prefix("Run ID: ") {
+                        run_id = Some(id.trim().to_string());
+                    }
+                }
+
+                let timestamp = timestamp.or_else(|| helpers::modified_time(&path));
+                if let Some(time) = time {
+                    if !filter.matches(timestamp, &three_gram, run_id.as_deref()) {
+                        continue;
                     }
+                    exact_frequency.push(time, timestamp);
                 }
             }
             Err(e) => println!("Error reading file: {:?}", e),
         }
     }
 
-    let average_exact_frequency_time = total_exact_frequency_time / count as f64;
-
-    let std_dev_exact_frequency =
-        helpers::calculate_std_dev(&exact_frequency_times, average_exact_frequency_time);
-
-    let median_exact_frequency_time = helpers::calculate_median(&mut exact_frequency_times);
-
-    let (min_exact_frequency_time, max_exact_frequency_time) =
-        helpers::calculate_min_max(&exact_frequency_times);
-
-    let percentile_90_exact = helpers::calculate_percentile(&exact_frequency_times, 90);
+    Ok(OperationSamples {
+        operation: String::from("insert"),
+        metrics: vec![exact_frequency],
+    })
+}
 
-    let throughput_exact_frequency = count as f64 / total_exact_frequency_time;
+/// Summarizes the samples of an operation, writes the report and its raw
+/// samples under `/home/projekt/stats/<operation>/` and prints the report.
+pub fn create_stats(
+    samples: &OperationSamples,
+    filter: &filter::StatsFilter,
+) -> Result<(), Box<dyn Error>> {
+    if samples.metrics.iter().all(|metric| metric.times.is_empty()) {
+        println!(
+            "No {} query results match the given filters",
+            samples.operation
+        );
+        return Ok(());
+    }
 
     let utc: DateTime<Utc> = Utc::now();
     let formated_date_time = utc.format("%Y-%m-%dT%H:%M:%S").to_string();
-    let output_file_path = String::from("/home/projekt/stats/insert/") + &formated_date_time;
+    let output_file_path =
+        String::from("/home/projekt/stats/") + &samples.operation + "/" + &formated_date_time;
 
     let mut output: Vec<String> = Vec::new();
+    let mut snapshot = snapshot::StatsSnapshot::new(samples.operation.clone(), formated_date_time);
 
-    let mut tmp_string = String::new();
-    write!(
-        &mut tmp_string,
-        "Average Time Taken for Exact Frequency: {:.3} seconds (Std Dev: {:.3})",
-        average_exact_frequency_time, std_dev_exact_frequency
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "Median Time Taken for Exact Frequency: {:.3} seconds",
-        median_exact_frequency_time
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "Min/Max Time Taken for Exact Frequency: {:.3}/{:.3} seconds",
-        min_exact_frequency_time, max_exact_frequency_time
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "90th Percentile Time for Exact Frequency: {:.3} seconds",
-        percentile_90_exact
-    )?;
-    output.push(tmp_string.clone());
-    tmp_string.clear();
-    write!(
-        &mut tmp_string,
-        "Throughput for Exact Frequency: {:.3} queries/second",
-        throughput_exact_frequency
-    )?;
-    output.push(tmp_string.clone());
+    for metric in &samples.metrics {
+        let summary = summary::LatencySummary::from_samples(&metric.times);
+        metric.write_lines(&summary, &mut output)?;
+        snapshot.add_metric(&metric.name, summary.samples());
+    }
+    for metric in &samples.metrics {
+        output.extend(timeseries::time_series_lines(
+            &metric.label,
+            &metric.series,
+            filter.bucket_seconds,
+        )?);
+    }
 
     let option = writer::WriteOptions::FILE(output_file_path.to_string());
     let result = writer::write_stats(option, &output);
@@ -250,8 +274,16 @@ pub fn create_insert_stats() -> Result<(), Box<dyn Error>> {
         println!("Error writing to file: {:?}", e);
         return Ok(());
     }
-    println!("Statistics for INSERT queries: ");
-    println!("");
+    let option = writer::WriteOptions::FILE(output_file_path.to_string() + ".json");
+    if let Err(e) = writer::write_stats_snapshot(option, &snapshot) {
+        println!("Error writing to file: {:?}", e);
+        return Ok(());
+    }
+    println!(
+        "Statistics for {} queries: ",
+        samples.operation.to_uppercase()
+    );
+    println!();
     for line in output {
         println!("{}", line);
     }
@@ -259,3 +291,38 @@ pub fn create_insert_stats() -> Result<(), Box<dyn Error>> {
     println!("{}", output_file_path);
     Ok(())
 }
+
+pub fn create_select_stats(filter: &filter::StatsFilter) -> Result<(), Box<dyn Error>> {
+    let samples = read_select_samples(filter)?;
+    create_stats(&samples, filter)
+}
+
+pub fn create_insert_stats(filter: &filter::StatsFilter) -> Result<(), Box<dyn Error>> {
+    let samples = read_insert_samples(filter)?;
+    create_stats(&samples, filter)
+}
+
+/// Writes the select or insert summaries in the Prometheus text format to
+/// `/home/projekt/stats/<operation>/<timestamp>.prom`.
+pub fn export_prometheus(
+    operation: &str,
+    filter: &filter::StatsFilter,
+) -> Result<(), Box<dyn Error>> {
+    let samples = match operation {
+        "select" => read_select_samples(filter)?,
+        "insert" => read_insert_samples(filter)?,
+        _ => return Err(format!("Unknown operation: {}", operation).into()),
+    };
+    let output = prometheus::render(&samples)?;
+
+    let utc: DateTime<Utc> = Utc::now();
+    let formated_date_time = utc.format("%Y-%m-%dT%H:%M:%S").to_string();
+    let output_file_path =
+        String::from("/home/projekt/stats/") + operation + "/" + &formated_date_time + ".prom";
+    fs::write(&output_file_path, &output)?;
+
+    print!("{}", output);
+    println!("\nThis information can also be found in file:");
+    println!("{}", output_file_path);
+    Ok(())
+}
@@ -0,0 +1,313 @@
+use super::helpers;
+use super::snapshot::StatsSnapshot;
+use super::summary::LatencySummary;
+use core::fmt::Write;
+use std::error::Error;
+
+static DEFAULT_THRESHOLD_PERCENT: f64 = 10.0;
+static DEFAULT_SIGNIFICANCE_LEVEL: f64 = 0.05;
+
+pub struct DiffOptions {
+    pub run_a: String,
+    pub run_b: String,
+    pub threshold_percent: f64,
+    pub significance_level: f64,
+}
+
+pub struct MetricDiff {
+    pub name: String,
+    pub samples_a: usize,
+    pub samples_b: usize,
+    pub mean: (f64, f64),
+    pub median: (f64, f64),
+    pub percentile_90: (f64, f64),
+    pub percentile_99: (f64, f64),
+    pub throughput: (f64, f64),
+    pub p_value: Option<f64>,
+    pub regression: bool,
+}
+
+impl DiffOptions {
+    /// Parses `<run-a> <run-b> [--threshold <percent>] [--alpha <level>]`.
+    pub fn from(args: &[String]) -> Result<DiffOptions, String> {
+        let mut runs: Vec<String> = Vec::new();
+        let mut threshold_percent = DEFAULT_THRESHOLD_PERCENT;
+        let mut significance_level = DEFAULT_SIGNIFICANCE_LEVEL;
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--threshold" => {
+                    threshold_percent = iter
+                        .next()
+                        .and_then(|value| value.parse::<f64>().ok())
+                        .ok_or("--threshold expects a percentage")?;
+                }
+                "--alpha" => {
+                    significance_level = iter
+                        .next()
+                        .and_then(|value| value.parse::<f64>().ok())
+                        .ok_or("--alpha expects a significance level")?;
+                }
+                _ if !arg.starts_with("--") => runs.push(arg.clone()),
+                _ => return Err(format!("Unknown diff option: {}", arg)),
+            }
+        }
+
+        if runs.len() != 2 {
+            return Err(
+                "Usage: stats diff <run-a> <run-b> [--threshold <percent>] [--alpha <level>]"
+                    .to_string(),
+            );
+        }
+
+        let run_b = runs.pop().unwrap();
+        let run_a = runs.pop().unwrap();
+        Ok(DiffOptions {
+            run_a,
+            run_b,
+            threshold_percent,
+            significance_level,
+        })
+    }
+}
+
+/// Change from `before` to `after` in percent. Any growth from zero is an
+/// infinite change, so a slowdown from a zero median is still flagged.
+fn relative_change(before: f64, after: f64) -> f64 {
+    if before == 0.0 {
+        return if after > 0.0 { f64::INFINITY } else { 0.0 };
+    }
+    (after - before) / before * 100.0
+}
+
+pub fn compare(
+    snapshot_a: &StatsSnapshot,
+    snapshot_b: &StatsSnapshot,
+    options: &DiffOptions,
+) -> Vec<MetricDiff> {
+    let mut diffs = Vec::new();
+
+    for (name, samples_a) in &snapshot_a.metrics {
+        let samples_b = match snapshot_b.metrics.get(name) {
+            Some(samples_b) => samples_b,
+            None => continue,
+        };
+        let summary_a = LatencySummary::from_samples(samples_a);
+        let summary_b = LatencySummary::from_samples(samples_b);
+        let p_value =
+            helpers::calculate_mann_whitney_p_value(summary_a.samples(), summary_b.samples());
+        let regression = match p_value {
+            Some(p_value) => {
+                p_value < options.significance_level
+                    && relative_change(summary_a.median, summary_b.median)
+                        > options.threshold_percent
+            }
+            None => false,
+        };
+
+        diffs.push(MetricDiff {
+            name: name.clone(),
+            samples_a: summary_a.count,
+            samples_b: summary_b.count,
+            mean: (summary_a.mean, summary_b.mean),
+            median: (summary_a.median, summary_b.median),
+            percentile_90: (summary_a.percentile_90, summary_b.percentile_90),
+            percentile_99: (summary_a.percentile_99, summary_b.percentile_99),
+            throughput: (summary_a.throughput, summary_b.throughput),
+            p_value,
+            regression,
+        });
+    }
+
+    diffs
+}
+
+fn write_delta(
+    output: &mut Vec<String>,
+    label: &str,
+    unit: &str,
+    values: (f64, f64),
+) -> Result<(), core::fmt::Error> {
+    let mut tmp_string = String::new();
+    write!(
+        &mut tmp_string,
+        "  {}: {:.3} -> {:.3} {} ({:+.1}%)",
+        label,
+        values.0,
+        values.1,
+        unit,
+        relative_change(values.0, values.1)
+    )?;
+    output.push(tmp_string);
+    Ok(())
+}
+
+/// Compares two stats runs and returns the process exit code: 1 when any
+/// metric regressed beyond the threshold, 0 otherwise.
+pub fn run_diff(args: &[String]) -> Result<i32, Box<dyn Error>> {
+    let options = DiffOptions::from(args)?;
+    let snapshot_a = StatsSnapshot::load(&options.run_a)?;
+    let snapshot_b = StatsSnapshot::load(&options.run_b)?;
+
+    if snapshot_a.operation != snapshot_b.operation {
+        return Err(format!(
+            "Cannot compare {} stats with {} stats",
+            snapshot_a.operation, snapshot_b.operation
+        )
+        .into());
+    }
+
+    let diffs = compare(&snapshot_a, &snapshot_b, &options);
+    let mut output: Vec<String> = Vec::new();
+    let mut tmp_string = String::new();
+    write!(
+        &mut tmp_string,
+        "Comparing {} stats: {} -> {}",
+        snapshot_a.operation, snapshot_a.created_at, snapshot_b.created_at
+    )?;
+    output.push(tmp_string.clone());
+
+    for diff in &diffs {
+        tmp_string.clear();
+        write!(
+            &mut tmp_string,
+            "--- {} ({} vs {} samples) ---",
+            diff.name, diff.samples_a, diff.samples_b
+        )?;
+        output.push(tmp_string.clone());
+        write_delta(&mut output, "Mean", "seconds", diff.mean)?;
+        write_delta(&mut output, "Median", "seconds", diff.median)?;
+        write_delta(
+            &mut output,
+            "90th Percentile",
+            "seconds",
+            diff.percentile_90,
+        )?;
+        write_delta(
+            &mut output,
+            "99th Percentile",
+            "seconds",
+            diff.percentile_99,
+        )?;
+        write_delta(&mut output, "Throughput", "queries/second", diff.throughput)?;
+        tmp_string.clear();
+        match diff.p_value {
+            Some(p_value) => write!(
+                &mut tmp_string,
+                "  Mann-Whitney p-value: {:.4}{}",
+                p_value,
+                if diff.regression { " REGRESSION" } else { "" }
+            )?,
+            None => write!(&mut tmp_string, "  Mann-Whitney p-value: n/a")?,
+        }
+        output.push(tmp_string.clone());
+    }
+
+    for line in output {
+        println!("{}", line);
+    }
+
+    let regressions = diffs.iter().filter(|diff| diff.regression).count();
+    if regressions > 0 {
+        println!(
+            "\n{} metric(s) regressed by more than {:.1}%",
+            regressions, options.threshold_percent
+        );
+        return Ok(1);
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(threshold_percent: f64) -> DiffOptions {
+        DiffOptions {
+            run_a: String::from("a"),
+            run_b: String::from("b"),
+            threshold_percent,
+            significance_level: DEFAULT_SIGNIFICANCE_LEVEL,
+        }
+    }
+
+    fn snapshot(samples: &[(&str, Vec<f64>)]) -> StatsSnapshot {
+        let mut snapshot = StatsSnapshot::new(String::from("select"), String::from("now"));
+        for (name, values) in samples {
+            snapshot.metrics.insert(name.to_string(), values.clone());
+        }
+        snapshot
+    }
+
+    #[test]
+    fn relative_change_is_a_percentage_of_the_first_value() {
+        assert_eq!(relative_change(2.0, 3.0), 50.0);
+        assert_eq!(relative_change(2.0, 1.0), -50.0);
+        assert_eq!(relative_change(0.0, 1.0), f64::INFINITY);
+        assert_eq!(relative_change(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    fn options_take_two_runs_and_optional_levels() {
+        let args: Vec<String> = ["a", "--threshold", "5", "b", "--alpha", "0.01"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let options = DiffOptions::from(&args).unwrap();
+        assert_eq!((options.run_a.as_str(), options.run_b.as_str()), ("a", "b"));
+        assert_eq!(options.threshold_percent, 5.0);
+        assert_eq!(options.significance_level, 0.01);
+
+        assert!(DiffOptions::from(&[String::from("a")]).is_err());
+        assert!(DiffOptions::from(&[String::from("a"), String::from("--alpha")]).is_err());
+        let unknown: Vec<String> = ["a", "b", "--treshold", "5"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert_eq!(
+            DiffOptions::from(&unknown).err().unwrap(),
+            "Unknown diff option: --treshold"
+        );
+    }
+
+    #[test]
+    fn compare_flags_significant_slowdowns_beyond_the_threshold() {
+        let fast: Vec<f64> = (0..20).map(|i| 1.0 + i as f64 * 0.01).collect();
+        let slow: Vec<f64> = fast.iter().map(|time| time * 2.0).collect();
+        let a = snapshot(&[("get", fast.clone()), ("only_a", fast.clone())]);
+        let b = snapshot(&[("get", slow.clone())]);
+
+        let diffs = compare(&a, &b, &options(10.0));
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].name, "get");
+        assert!(diffs[0].regression);
+
+        // A speed-up is significant as well, but not a regression.
+        let diffs = compare(&b, &a, &options(10.0));
+        assert!(diffs[0].p_value.unwrap() < DEFAULT_SIGNIFICANCE_LEVEL);
+        assert!(!diffs[0].regression);
+
+        // Nor is a slowdown below the threshold.
+        let diffs = compare(&a, &b, &options(200.0));
+        assert!(!diffs[0].regression);
+    }
+
+    #[test]
+    fn compare_flags_slowdowns_from_a_zero_median() {
+        let zero = vec![0.0; 20];
+        let slow: Vec<f64> = (1..=20).map(|i| i as f64 * 0.01).collect();
+        let a = snapshot(&[("get", zero)]);
+        let b = snapshot(&[("get", slow)]);
+        let diffs = compare(&a, &b, &options(10.0));
+        assert!(diffs[0].regression);
+    }
+
+    #[test]
+    fn compare_does_not_flag_identical_runs() {
+        let samples: Vec<f64> = (0..10).map(f64::from).collect();
+        let a = snapshot(&[("get", samples.clone())]);
+        let diffs = compare(&a, &a, &options(0.0));
+        assert!(!diffs[0].regression);
+    }
+}
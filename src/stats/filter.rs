@@ -0,0 +1,107 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use glob::Pattern;
+
+static DEFAULT_BUCKET_SECONDS: i64 = 60;
+
+/// Restricts which query results are aggregated into stats.
+pub struct StatsFilter {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub pattern: Option<Pattern>,
+    pub run_id: Option<String>,
+    pub bucket_seconds: i64,
+}
+
+fn parse_date_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date_time.with_timezone(&Utc));
+    }
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .map(|date_time| date_time.and_utc())
+        .map_err(|_| {
+            format!(
+                "Invalid date time: {} (expected YYYY-MM-DDTHH:MM:SS)",
+                value
+            )
+        })
+}
+
+impl Default for StatsFilter {
+    fn default() -> StatsFilter {
+        StatsFilter {
+            from: None,
+            to: None,
+            pattern: None,
+            run_id: None,
+            bucket_seconds: DEFAULT_BUCKET_SECONDS,
+        }
+    }
+}
+
+impl StatsFilter {
+    /// Parses `[--from <time>] [--to <time>] [--pattern <glob>] [--run-id <id>]
+    /// [--bucket <seconds>]`. The pattern is matched against "word_1 word_2 word_3".
+    pub fn from(args: &[String]) -> Result<StatsFilter, String> {
+        let mut filter = StatsFilter::default();
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            let value = iter
+                .next()
+                .ok_or(format!("{} expects a value", arg))?
+                .as_str();
+            match arg.as_str() {
+                "--from" => filter.from = Some(parse_date_time(value)?),
+                "--to" => filter.to = Some(parse_date_time(value)?),
+                "--pattern" => {
+                    filter.pattern = Some(
+                        Pattern::new(value)
+                            .map_err(|err| format!("Invalid pattern {}: {}", value, err))?,
+                    )
+                }
+                "--run-id" => filter.run_id = Some(value.to_string()),
+                "--bucket" => {
+                    filter.bucket_seconds = value
+                        .parse::<i64>()
+                        .ok()
+                        .filter(|seconds| *seconds > 0)
+                        .ok_or("--bucket expects a positive number of seconds")?
+                }
+                _ => return Err(format!("Unknown filter: {}", arg)),
+            }
+        }
+
+        Ok(filter)
+    }
+
+    pub fn matches(
+        &self,
+        timestamp: Option<DateTime<Utc>>,
+        three_gram: &str,
+        run_id: Option<&str>,
+    ) -> bool {
+        if self.from.is_some() || self.to.is_some() {
+            let timestamp = match timestamp {
+                Some(timestamp) => timestamp,
+                None => return false,
+            };
+            if self.from.is_some_and(|from| timestamp < from) {
+                return false;
+            }
+            if self.to.is_some_and(|to| timestamp > to) {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.pattern {
+            if !pattern.matches(three_gram) {
+                return false;
+            }
+        }
+        if let Some(expected) = &self.run_id {
+            if run_id != Some(expected.as_str()) {
+                return false;
+            }
+        }
+        true
+    }
+}
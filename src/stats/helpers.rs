@@ -1,3 +1,7 @@
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::Path;
+
 pub fn parse_time_from_line_select(line: &str) -> Option<f64> {
     line.split("seconds")
         .next()?
@@ -11,6 +15,9 @@ pub fn parse_time_from_line_insert(line: &str) -> Option<f64> {
     line.split(" ").nth(8).and_then(|s| s.parse::<f64>().ok())
 }
 pub fn calculate_std_dev(times: &[f64], mean: f64) -> f64 {
+    if times.is_empty() {
+        return 0.0;
+    }
     let variance = times
         .iter()
         .map(|&time| {
@@ -23,15 +30,21 @@ pub fn calculate_std_dev(times: &[f64], mean: f64) -> f64 {
     variance.sqrt()
 }
 pub fn calculate_median(times: &mut [f64]) -> f64 {
-    times.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    if times.is_empty() {
+        return 0.0;
+    }
+    times.sort_unstable_by(|a, b| a.total_cmp(b));
     let mid = times.len() / 2;
-    if times.len() % 2 == 0 {
+    if times.len().is_multiple_of(2) {
         (times[mid - 1] + times[mid]) / 2.0
     } else {
         times[mid]
     }
 }
 pub fn calculate_min_max(times: &[f64]) -> (f64, f64) {
+    if times.is_empty() {
+        return (0.0, 0.0);
+    }
     times
         .iter()
         .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &val| {
@@ -39,8 +52,195 @@ pub fn calculate_min_max(times: &[f64]) -> (f64, f64) {
         })
 }
 pub fn calculate_percentile(times: &[f64], percentile: u32) -> f64 {
+    if times.is_empty() {
+        return 0.0;
+    }
     let mut sorted_times = times.to_vec();
-    sorted_times.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+    sorted_times.sort_unstable_by(|a, b| a.total_cmp(b));
+    let percentile = percentile.min(100);
     let index = (percentile as f64 / 100.0 * (sorted_times.len() - 1) as f64).round() as usize;
     sorted_times[index]
 }
+pub fn parse_row_count_from_line(line: &str) -> Option<f64> {
+    line.split('(')
+        .nth(1)?
+        .split(" rows)")
+        .next()?
+        .trim()
+        .parse::<f64>()
+        .ok()
+}
+pub fn calculate_correlation(xs: &[f64], ys: &[f64]) -> f64 {
+    let n = xs.len().min(ys.len());
+    if n < 2 {
+        return 0.0;
+    }
+    let mean_x = xs[..n].iter().sum::<f64>() / n as f64;
+    let mean_y = ys[..n].iter().sum::<f64>() / n as f64;
+    let mut covariance = 0.0;
+    let mut variance_x = 0.0;
+    let mut variance_y = 0.0;
+    for i in 0..n {
+        let diff_x = xs[i] - mean_x;
+        let diff_y = ys[i] - mean_y;
+        covariance += diff_x * diff_y;
+        variance_x += diff_x * diff_x;
+        variance_y += diff_y * diff_y;
+    }
+    if variance_x == 0.0 || variance_y == 0.0 {
+        return 0.0;
+    }
+    covariance / (variance_x.sqrt() * variance_y.sqrt())
+}
+pub fn calculate_mean(times: &[f64]) -> f64 {
+    if times.is_empty() {
+        return 0.0;
+    }
+    times.iter().sum::<f64>() / times.len() as f64
+}
+pub fn calculate_throughput(times: &[f64]) -> f64 {
+    let total: f64 = times.iter().sum();
+    if total == 0.0 {
+        return 0.0;
+    }
+    times.len() as f64 / total
+}
+pub fn normal_cdf(x: f64) -> f64 {
+    // Abramowitz and Stegun 7.1.26, accurate to about 1.5e-7.
+    let z = x.abs() / 2.0_f64.sqrt();
+    let t = 1.0 / (1.0 + 0.3275911 * z);
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-z * z).exp();
+    if x >= 0.0 {
+        0.5 * (1.0 + erf)
+    } else {
+        0.5 * (1.0 - erf)
+    }
+}
+/// The Mann-Whitney U statistic of `first` against `second`, with tied values
+/// sharing their average rank, and the tie correction `sum(t^3 - t)` over the
+/// groups of `t` tied values.
+pub fn calculate_mann_whitney_u(first: &[f64], second: &[f64]) -> (f64, f64) {
+    let n1 = first.len() as f64;
+    let mut combined: Vec<(f64, bool)> = first
+        .iter()
+        .map(|&time| (time, true))
+        .chain(second.iter().map(|&time| (time, false)))
+        .collect();
+    combined.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let n = combined.len();
+    let mut rank_sum_first = 0.0;
+    let mut tie_correction = 0.0;
+    let mut i = 0;
+    while i < n {
+        let mut j = i;
+        while j + 1 < n && combined[j + 1].0 == combined[i].0 {
+            j += 1;
+        }
+        let ties = (j - i + 1) as f64;
+        let average_rank = (i + j) as f64 / 2.0 + 1.0;
+        for item in &combined[i..=j] {
+            if item.1 {
+                rank_sum_first += average_rank;
+            }
+        }
+        tie_correction += ties * ties * ties - ties;
+        i = j + 1;
+    }
+
+    let u = rank_sum_first - n1 * (n1 + 1.0) / 2.0;
+    (u, tie_correction)
+}
+pub fn calculate_mann_whitney_p_value(first: &[f64], second: &[f64]) -> Option<f64> {
+    let n1 = first.len() as f64;
+    let n2 = second.len() as f64;
+    if first.is_empty() || second.is_empty() {
+        return None;
+    }
+    let (u, tie_correction) = calculate_mann_whitney_u(first, second);
+    let n = (first.len() + second.len()) as f64;
+    let mean_u = n1 * n2 / 2.0;
+    let variance_u = n1 * n2 / 12.0 * ((n + 1.0) - tie_correction / (n * (n - 1.0)));
+    if variance_u <= 0.0 {
+        return Some(1.0);
+    }
+    let z = (u - mean_u) / variance_u.sqrt();
+    Some(2.0 * (1.0 - normal_cdf(z.abs())))
+}
+pub fn parse_three_gram_from_line(line: &str) -> Option<String> {
+    let three_gram = line.split(": ").nth(1)?.split(" = ").next()?.trim();
+    Some(three_gram.to_string())
+}
+pub fn parse_timestamp_from_line(line: &str) -> Option<DateTime<Utc>> {
+    let timestamp = line.strip_prefix("Timestamp:")?.trim();
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+pub fn modified_time(path: &Path) -> Option<DateTime<Utc>> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(DateTime::<Utc>::from(modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mann_whitney_u_counts_pairs_won_by_the_second_sample() {
+        assert_eq!(
+            calculate_mann_whitney_u(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]),
+            (0.0, 0.0)
+        );
+        assert_eq!(
+            calculate_mann_whitney_u(&[4.0, 5.0, 6.0], &[1.0, 2.0, 3.0]),
+            (9.0, 0.0)
+        );
+        assert_eq!(
+            calculate_mann_whitney_u(&[1.0, 4.0], &[2.0, 3.0]),
+            (2.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn mann_whitney_u_gives_ties_their_average_rank() {
+        // 1 2 2 | 2 3: the three 2s share rank 3, so the first sample has rank
+        // sum 1 + 3 + 3 = 7 and U = 7 - 6 = 1, with one group of three ties.
+        assert_eq!(
+            calculate_mann_whitney_u(&[1.0, 2.0, 2.0], &[2.0, 3.0]),
+            (1.0, 24.0)
+        );
+    }
+
+    #[test]
+    fn mann_whitney_p_value_needs_both_samples() {
+        assert_eq!(calculate_mann_whitney_p_value(&[], &[1.0]), None);
+        assert_eq!(calculate_mann_whitney_p_value(&[1.0], &[]), None);
+    }
+
+    #[test]
+    fn mann_whitney_p_value_is_one_when_every_value_is_tied() {
+        assert_eq!(
+            calculate_mann_whitney_p_value(&[2.0, 2.0], &[2.0, 2.0]),
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn mann_whitney_p_value_separates_shifted_samples() {
+        let first: Vec<f64> = (0..20).map(f64::from).collect();
+        let second: Vec<f64> = (100..120).map(f64::from).collect();
+        let p_value = calculate_mann_whitney_p_value(&first, &second).unwrap();
+        assert!(p_value < 0.001);
+        assert_eq!(
+            calculate_mann_whitney_p_value(&second, &first),
+            Some(p_value)
+        );
+
+        let same = calculate_mann_whitney_p_value(&first, &first).unwrap();
+        assert!((same - 1.0).abs() < 1e-6);
+    }
+}
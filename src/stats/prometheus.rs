@@ -0,0 +1,43 @@
+use super::summary::LatencySummary;
+use super::OperationSamples;
+use core::fmt::{self, Write};
+
+/// Renders the latency summaries of an operation as a Prometheus summary
+/// metric named `three_gram_<operation>_latency_seconds`.
+pub fn render(samples: &OperationSamples) -> Result<String, fmt::Error> {
+    let name = format!("three_gram_{}_latency_seconds", samples.operation);
+    let mut output = String::new();
+
+    writeln!(
+        &mut output,
+        "# HELP {} Latency of {} queries from stored query results.",
+        name, samples.operation
+    )?;
+    writeln!(&mut output, "# TYPE {} summary", name)?;
+    for metric in &samples.metrics {
+        let summary = LatencySummary::from_samples(&metric.times);
+        for (quantile, value) in [
+            ("0.5", summary.median),
+            ("0.9", summary.percentile_90),
+            ("0.99", summary.percentile_99),
+        ] {
+            writeln!(
+                &mut output,
+                "{}{{metric=\"{}\",quantile=\"{}\"}} {}",
+                name, metric.name, quantile, value
+            )?;
+        }
+        writeln!(
+            &mut output,
+            "{}_sum{{metric=\"{}\"}} {}",
+            name, metric.name, summary.total
+        )?;
+        writeln!(
+            &mut output,
+            "{}_count{{metric=\"{}\"}} {}",
+            name, metric.name, summary.count
+        )?;
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Raw latency samples behind one stats run, keyed by metric name.
+#[derive(Serialize, Deserialize)]
+pub struct StatsSnapshot {
+    pub operation: String,
+    pub created_at: String,
+    pub metrics: BTreeMap<String, Vec<f64>>,
+}
+
+impl StatsSnapshot {
+    pub fn new(operation: String, created_at: String) -> StatsSnapshot {
+        StatsSnapshot {
+            operation,
+            created_at,
+            metrics: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_metric(&mut self, name: &str, samples: &[f64]) {
+        self.metrics.insert(name.to_string(), samples.to_vec());
+    }
+
+    /// Loads a snapshot by path, also accepting the path of the plain text
+    /// stats file written alongside it.
+    pub fn load(path: &str) -> Result<StatsSnapshot, Box<dyn Error>> {
+        let json_path = String::from(path) + ".json";
+        let path = if path.ends_with(".json") || !Path::new(&json_path).exists() {
+            path.to_string()
+        } else {
+            json_path
+        };
+        let content = fs::read_to_string(&path)
+            .map_err(|err| format!("Could not read snapshot {}: {}", path, err))?;
+        let snapshot: StatsSnapshot = serde_json::from_str(&content)
+            .map_err(|err| format!("Could not parse snapshot {}: {}", path, err))?;
+        Ok(snapshot)
+    }
+}
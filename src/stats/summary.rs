@@ -0,0 +1,206 @@
+use super::helpers;
+use core::fmt::{self, Write};
+
+/// Summary of a set of latency samples in seconds. Non-finite and negative
+/// samples are discarded and counted separately; every statistic of an empty
+/// summary is 0.
+pub struct LatencySummary {
+    pub count: usize,
+    pub discarded: usize,
+    pub total: f64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+    pub percentile_90: f64,
+    pub percentile_99: f64,
+    pub throughput: f64,
+    sorted: Vec<f64>,
+}
+
+impl LatencySummary {
+    pub fn from_samples(samples: &[f64]) -> LatencySummary {
+        let mut sorted: Vec<f64> = samples
+            .iter()
+            .copied()
+            .filter(|time| time.is_finite() && *time >= 0.0)
+            .collect();
+        sorted.sort_unstable_by(|a, b| a.total_cmp(b));
+
+        let count = sorted.len();
+        let discarded = samples.len() - count;
+        let total: f64 = sorted.iter().sum();
+        let mean = helpers::calculate_mean(&sorted);
+        let std_dev = helpers::calculate_std_dev(&sorted, mean);
+        let (min, max) = helpers::calculate_min_max(&sorted);
+
+        LatencySummary {
+            count,
+            discarded,
+            total,
+            mean,
+            std_dev,
+            median: helpers::calculate_median(&mut sorted),
+            min,
+            max,
+            percentile_90: helpers::calculate_percentile(&sorted, 90),
+            percentile_99: helpers::calculate_percentile(&sorted, 99),
+            throughput: helpers::calculate_throughput(&sorted),
+            sorted,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The accepted samples in ascending order.
+    pub fn samples(&self) -> &[f64] {
+        &self.sorted
+    }
+
+    pub fn write_lines(&self, label: &str, output: &mut Vec<String>) -> Result<(), fmt::Error> {
+        let mut tmp_string = String::new();
+        write!(&mut tmp_string, "Samples for {}: {}", label, self.count)?;
+        if self.discarded > 0 {
+            write!(
+                &mut tmp_string,
+                " ({} invalid samples discarded)",
+                self.discarded
+            )?;
+        }
+        output.push(tmp_string.clone());
+        if self.is_empty() {
+            return Ok(());
+        }
+        tmp_string.clear();
+        write!(
+            &mut tmp_string,
+            "Average Time Taken for {}: {:.3} seconds (Std Dev: {:.3})",
+            label, self.mean, self.std_dev
+        )?;
+        output.push(tmp_string.clone());
+        tmp_string.clear();
+        write!(
+            &mut tmp_string,
+            "Median Time Taken for {}: {:.3} seconds",
+            label, self.median
+        )?;
+        output.push(tmp_string.clone());
+        tmp_string.clear();
+        write!(
+            &mut tmp_string,
+            "Min/Max Time Taken for {}: {:.3}/{:.3} seconds",
+            label, self.min, self.max
+        )?;
+        output.push(tmp_string.clone());
+        tmp_string.clear();
+        write!(
+            &mut tmp_string,
+            "90th Percentile Time for {}: {:.3} seconds",
+            label, self.percentile_90
+        )?;
+        output.push(tmp_string.clone());
+        tmp_string.clear();
+        write!(
+            &mut tmp_string,
+            "99th Percentile Time for {}: {:.3} seconds",
+            label, self.percentile_99
+        )?;
+        output.push(tmp_string.clone());
+        tmp_string.clear();
+        write!(
+            &mut tmp_string,
+            "Throughput for {}: {:.3} queries/second",
+            label, self.throughput
+        )?;
+        output.push(tmp_string.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_samples_give_an_all_zero_summary() {
+        let summary = LatencySummary::from_samples(&[]);
+        assert!(summary.is_empty());
+        assert_eq!((summary.count, summary.discarded), (0, 0));
+        for value in [
+            summary.total,
+            summary.mean,
+            summary.std_dev,
+            summary.median,
+            summary.min,
+            summary.max,
+            summary.percentile_90,
+            summary.percentile_99,
+            summary.throughput,
+        ] {
+            assert_eq!(value, 0.0);
+        }
+
+        let mut output = Vec::new();
+        summary.write_lines("select", &mut output).unwrap();
+        assert_eq!(output, vec!["Samples for select: 0"]);
+    }
+
+    #[test]
+    fn a_single_sample_is_every_statistic() {
+        let summary = LatencySummary::from_samples(&[0.5]);
+        assert_eq!(summary.count, 1);
+        assert_eq!(summary.std_dev, 0.0);
+        for value in [
+            summary.mean,
+            summary.median,
+            summary.min,
+            summary.max,
+            summary.percentile_90,
+            summary.percentile_99,
+        ] {
+            assert_eq!(value, 0.5);
+        }
+        assert_eq!(summary.throughput, 2.0);
+    }
+
+    #[test]
+    fn non_finite_and_negative_samples_are_discarded() {
+        let summary =
+            LatencySummary::from_samples(&[0.4, f64::NAN, 0.1, f64::INFINITY, -1.0, 0.3, 0.2]);
+        assert_eq!((summary.count, summary.discarded), (4, 3));
+        assert_eq!(summary.samples(), &[0.1, 0.2, 0.3, 0.4]);
+        assert!((summary.mean - 0.25).abs() < 1e-12);
+        assert!((summary.median - 0.25).abs() < 1e-12);
+        assert_eq!((summary.min, summary.max), (0.1, 0.4));
+        assert_eq!(summary.percentile_90, 0.4);
+        assert!((summary.throughput - 4.0).abs() < 1e-12);
+
+        let mut output = Vec::new();
+        summary.write_lines("select", &mut output).unwrap();
+        assert_eq!(
+            output[0],
+            "Samples for select: 4 (3 invalid samples discarded)"
+        );
+    }
+
+    #[test]
+    fn only_invalid_samples_give_an_empty_summary() {
+        let summary = LatencySummary::from_samples(&[f64::NAN, f64::NEG_INFINITY]);
+        assert!(summary.is_empty());
+        assert_eq!(summary.discarded, 2);
+        assert_eq!(summary.mean, 0.0);
+        assert_eq!(summary.throughput, 0.0);
+    }
+
+    #[test]
+    fn percentiles_round_to_the_nearest_sample() {
+        let samples: Vec<f64> = (1..=100).map(f64::from).collect();
+        let summary = LatencySummary::from_samples(&samples);
+        assert_eq!(summary.median, 50.5);
+        assert_eq!(summary.percentile_90, 90.0);
+        assert_eq!(summary.percentile_99, 99.0);
+    }
+}
@@ -0,0 +1,68 @@
+use chrono::{DateTime, TimeZone, Utc};
+use core::fmt::Write;
+use std::collections::BTreeMap;
+
+pub struct TimeBucket {
+    pub start: DateTime<Utc>,
+    pub count: usize,
+    pub total_time: f64,
+}
+
+impl TimeBucket {
+    pub fn mean_time(&self) -> f64 {
+        self.total_time / self.count as f64
+    }
+}
+
+/// Groups `(timestamp, latency)` samples into consecutive buckets of
+/// `bucket_seconds`, skipping empty buckets.
+pub fn bucket_samples(samples: &[(DateTime<Utc>, f64)], bucket_seconds: i64) -> Vec<TimeBucket> {
+    let mut buckets: BTreeMap<i64, TimeBucket> = BTreeMap::new();
+
+    for (timestamp, time) in samples {
+        let key = timestamp.timestamp().div_euclid(bucket_seconds) * bucket_seconds;
+        let bucket = buckets.entry(key).or_insert_with(|| TimeBucket {
+            start: Utc.timestamp_opt(key, 0).unwrap(),
+            count: 0,
+            total_time: 0.0,
+        });
+        bucket.count += 1;
+        bucket.total_time += time;
+    }
+
+    buckets.into_values().collect()
+}
+
+pub fn time_series_lines(
+    label: &str,
+    samples: &[(DateTime<Utc>, f64)],
+    bucket_seconds: i64,
+) -> Result<Vec<String>, core::fmt::Error> {
+    let mut output: Vec<String> = Vec::new();
+    if samples.is_empty() {
+        return Ok(output);
+    }
+
+    let mut tmp_string = String::new();
+    write!(
+        &mut tmp_string,
+        "--- {} per {} seconds ---",
+        label, bucket_seconds
+    )?;
+    output.push(tmp_string.clone());
+
+    for bucket in bucket_samples(samples, bucket_seconds) {
+        tmp_string.clear();
+        write!(
+            &mut tmp_string,
+            "{}: {} queries ({:.3} queries/second), mean {:.3} seconds",
+            bucket.start.format("%Y-%m-%dT%H:%M:%S"),
+            bucket.count,
+            bucket.count as f64 / bucket_seconds as f64,
+            bucket.mean_time()
+        )?;
+        output.push(tmp_string.clone());
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,212 @@
+use crate::corpus;
+use crate::query_3_grams::{scan, specs};
+use chrono::{DateTime, SecondsFormat, Utc};
+use scylla::Session;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Instant;
+
+static CACHE_KEY: &str = "summary";
+static DEFAULT_TOP: usize = 20;
+static DEFAULT_PARALLELISM: usize = 8;
+static NUMBER_OF_FREQUENCIES_TO_PRINT: usize = 20;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+pub struct SummaryOptions {
+    pub format: Format,
+    pub top: usize,
+    pub parallelism: usize,
+    pub refresh: bool,
+}
+
+/// Corpus-wide statistics of the 3-grams of the active corpus, as of
+/// `computed_at`.
+#[derive(Serialize, Deserialize)]
+pub struct Summary {
+    pub distinct: u64,
+    pub token_mass: i64,
+    pub top: Vec<specs::ThreeGram>,
+    /// How many distinct 3-grams have each frequency.
+    pub freq_of_freqs: BTreeMap<i32, u64>,
+    pub computed_at: DateTime<Utc>,
+    pub time_taken: f64,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Result<Format, String> {
+        match name {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown summary format: {}", name)),
+        }
+    }
+}
+
+impl SummaryOptions {
+    /// Parses `[--format text|json] [--top <n>] [--parallelism <n>]
+    /// [--refresh]`. The cached summary is shown unless `--refresh` is given
+    /// or it holds fewer top 3-grams than asked for.
+    pub fn from(args: &[String]) -> Result<SummaryOptions, String> {
+        let mut format = Format::Text;
+        let mut top = DEFAULT_TOP;
+        let mut parallelism = DEFAULT_PARALLELISM;
+        let mut refresh = false;
+        let mut iter = args.iter();
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--format" => {
+                    format = Format::parse(iter.next().ok_or("--format expects a format")?)?
+                }
+                "--top" => {
+                    top = iter
+                        .next()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .ok_or("--top expects a number")?
+                }
+                "--parallelism" => {
+                    parallelism = iter
+                        .next()
+                        .and_then(|value| value.parse::<usize>().ok())
+                        .filter(|value| *value > 0)
+                        .ok_or("--parallelism expects a positive number")?
+                }
+                "--refresh" => refresh = true,
+                _ => return Err(format!("Unknown summary option: {}", arg)),
+            }
+        }
+
+        Ok(SummaryOptions {
+            format,
+            top,
+            parallelism,
+            refresh,
+        })
+    }
+}
+
+/// Scans the whole corpus and computes its summary, keeping only the `top`
+/// most frequent 3-grams in memory.
+pub async fn compute(
+    session: Arc<Session>,
+    top: usize,
+    parallelism: usize,
+) -> Result<Summary, Box<dyn Error>> {
+    let start_time = Instant::now();
+    let mut distinct = 0;
+    let mut token_mass = 0;
+    let mut freq_of_freqs = BTreeMap::new();
+    let mut heap = BinaryHeap::new();
+
+    let mut batches = scan::scan_all(session, parallelism);
+    while let Some(batch) = batches.recv().await {
+        for three_gram in batch? {
+            distinct += 1;
+            token_mass += three_gram.freq as i64;
+            *freq_of_freqs.entry(three_gram.freq).or_insert(0) += 1;
+            heap.push(Reverse((
+                three_gram.freq,
+                three_gram.word_1,
+                three_gram.word_2,
+                three_gram.word_3,
+            )));
+            if heap.len() > top {
+                heap.pop();
+            }
+        }
+    }
+
+    let top = heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((freq, word_1, word_2, word_3))| {
+            specs::ThreeGram::new(word_1, word_2, word_3, freq)
+        })
+        .collect();
+    Ok(Summary {
+        distinct,
+        token_mass,
+        top,
+        freq_of_freqs,
+        computed_at: Utc::now(),
+        time_taken: start_time.elapsed().as_secs_f64(),
+    })
+}
+
+/// Returns the cached summary of the active corpus, computing and caching
+/// it in the corpus metadata when there is none or `options` asks for it.
+pub async fn summary(
+    session: Session,
+    options: &SummaryOptions,
+) -> Result<Summary, Box<dyn Error>> {
+    let corpus = corpus::active();
+    if !options.refresh {
+        if let Some(cached) = corpus::get_metadata(&session, corpus, CACHE_KEY).await? {
+            if let Ok(mut cached) = serde_json::from_str::<Summary>(&cached) {
+                if cached.top.len() >= options.top || cached.top.len() as u64 == cached.distinct {
+                    cached.top.truncate(options.top);
+                    return Ok(cached);
+                }
+            }
+        }
+    }
+
+    let session = Arc::new(session);
+    let summary = compute(session.clone(), options.top, options.parallelism).await?;
+    corpus::set_metadata(
+        &session,
+        corpus,
+        CACHE_KEY,
+        &serde_json::to_string(&summary)?,
+    )
+    .await?;
+    Ok(summary)
+}
+
+pub fn print(summary: &Summary, format: Format) -> Result<(), Box<dyn Error>> {
+    if format == Format::Json {
+        println!("{}", serde_json::to_string_pretty(summary)?);
+        return Ok(());
+    }
+
+    println!(
+        "Summary of corpus {} computed at {} in {:.3} seconds",
+        corpus::active().name,
+        summary
+            .computed_at
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+        summary.time_taken
+    );
+    println!("Distinct 3-grams: {}", summary.distinct);
+    println!("Token mass: {}", summary.token_mass);
+    println!("--- top {} 3-grams ---", summary.top.len());
+    for three_gram in &summary.top {
+        println!(
+            " {} {} {}: {}",
+            three_gram.word_1, three_gram.word_2, three_gram.word_3, three_gram.freq
+        );
+    }
+    println!("--- frequency of frequencies ---");
+    for (freq, count) in summary
+        .freq_of_freqs
+        .iter()
+        .take(NUMBER_OF_FREQUENCIES_TO_PRINT)
+    {
+        println!(" {}: {}", freq, count);
+    }
+    if summary.freq_of_freqs.len() > NUMBER_OF_FREQUENCIES_TO_PRINT {
+        println!(
+            " ... and {} more",
+            summary.freq_of_freqs.len() - NUMBER_OF_FREQUENCIES_TO_PRINT
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use regex::Regex;
+use std::error::Error;
+use std::sync::OnceLock;
+use unicode_segmentation::UnicodeSegmentation;
+
+static ACTIVE: OnceLock<Box<dyn Tokenizer>> = OnceLock::new();
+
+/// Splits text into the words 3-grams are built from.
+pub trait Tokenizer: Send + Sync {
+    fn tokenize(&self, text: &str) -> Vec<String>;
+}
+
+/// Splits on whitespace, keeping punctuation attached to words.
+pub struct WhitespaceTokenizer;
+
+/// Splits on Unicode word boundaries (UAX #29), dropping punctuation and
+/// whitespace. Works for scripts that don't separate words with spaces.
+pub struct UnicodeWordTokenizer;
+
+/// Uses every match of a regular expression as a token.
+pub struct RegexTokenizer {
+    regex: Regex,
+}
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+}
+
+impl Tokenizer for UnicodeWordTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.unicode_words().map(String::from).collect()
+    }
+}
+
+impl RegexTokenizer {
+    pub fn new(pattern: &str) -> Result<RegexTokenizer, regex::Error> {
+        Ok(RegexTokenizer {
+            regex: Regex::new(pattern)?,
+        })
+    }
+}
+
+impl Tokenizer for RegexTokenizer {
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        self.regex
+            .find_iter(text)
+            .map(|token| token.as_str().to_string())
+            .filter(|token| !token.is_empty())
+            .collect()
+    }
+}
+
+/// Builds a tokenizer from `whitespace`, `unicode` or `regex=<pattern>`.
+pub fn from_spec(spec: &str) -> Result<Box<dyn Tokenizer>, String> {
+    match spec {
+        "whitespace" => Ok(Box::new(WhitespaceTokenizer)),
+        "unicode" => Ok(Box::new(UnicodeWordTokenizer)),
+        _ => match spec.strip_prefix("regex=") {
+            Some(pattern) => RegexTokenizer::new(pattern)
+                .map(|tokenizer| Box::new(tokenizer) as Box<dyn Tokenizer>)
+                .map_err(|err| format!("Invalid tokenizer pattern {}: {}", pattern, err)),
+            None => Err(format!("Unknown tokenizer: {}", spec)),
+        },
+    }
+}
+
+/// Sets the process-wide tokenizer from `--tokenizer <spec>` or the
+/// `TOKENIZER` environment variable and returns `args` without the flag.
+pub fn init(args: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut remaining = Vec::new();
+    let mut spec = std::env::var("TOKENIZER").ok();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--tokenizer" {
+            spec = Some(iter.next().ok_or("--tokenizer expects a tokenizer")?);
+        } else {
+            remaining.push(arg);
+        }
+    }
+
+    let tokenizer = from_spec(spec.as_deref().unwrap_or("whitespace"))?;
+    _ = ACTIVE.set(tokenizer);
+    Ok(remaining)
+}
+
+pub fn active() -> &'static dyn Tokenizer {
+    ACTIVE
+        .get_or_init(|| Box::new(WhitespaceTokenizer))
+        .as_ref()
+}
@@ -0,0 +1,407 @@
+use crate::compare::{keyness, per_million};
+use crate::corpus;
+use crate::query_3_grams::{specs, statements};
+use chrono::{NaiveDate, Utc};
+use futures::StreamExt;
+use itertools::Itertools;
+use scylla::{IntoTypedRows, Session};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::OnceLock;
+
+static CONFIGURED: OnceLock<Config> = OnceLock::new();
+static PAGE_SIZE: i32 = 5000;
+static SHARDS: u32 = 16;
+
+/// The width of a time bucket. Buckets are named after the date they cover,
+/// `2024`, `2024-05` or `2024-05-17`, so they sort chronologically and the
+/// granularity of a bucket can be told from its name.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Granularity {
+    Day,
+    Month,
+    Year,
+}
+
+/// Which buckets are counted on insert and import and the date the counts
+/// are filed under, the current day unless a document date is given.
+struct Config {
+    granularities: Vec<Granularity>,
+    date: Option<NaiveDate>,
+}
+
+/// A 3-gram whose share of the tokens changed between two periods.
+pub struct Trend {
+    pub input: specs::ThreeGramInput,
+    pub freq_a: i64,
+    pub freq_b: i64,
+    pub log_likelihood: f64,
+}
+
+impl Granularity {
+    pub fn parse(name: &str) -> Result<Granularity, String> {
+        match name {
+            "day" => Ok(Granularity::Day),
+            "month" => Ok(Granularity::Month),
+            "year" => Ok(Granularity::Year),
+            _ => Err(format!("Unknown time bucket: {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Granularity::Day => "day",
+            Granularity::Month => "month",
+            Granularity::Year => "year",
+        }
+    }
+
+    pub fn bucket(&self, date: NaiveDate) -> String {
+        match self {
+            Granularity::Day => date.format("%Y-%m-%d").to_string(),
+            Granularity::Month => date.format("%Y-%m").to_string(),
+            Granularity::Year => date.format("%Y").to_string(),
+        }
+    }
+
+    /// The granularity of a bucket name, checking that it is a valid date.
+    pub fn of(bucket: &str) -> Result<Granularity, String> {
+        let granularity = match bucket.len() {
+            4 => Granularity::Year,
+            7 => Granularity::Month,
+            10 => Granularity::Day,
+            _ => return Err(format!("Invalid time bucket \"{}\"", bucket)),
+        };
+        let date = match granularity {
+            Granularity::Year => format!("{}-01-01", bucket),
+            Granularity::Month => format!("{}-01", bucket),
+            Granularity::Day => bucket.to_string(),
+        };
+        NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+            .map_err(|_| format!("Invalid time bucket \"{}\"", bucket))?;
+        Ok(granularity)
+    }
+}
+
+/// Sets the process-wide buckets from `--buckets <day,month,year>` or the
+/// `BUCKETS` environment variable and the document date from `--date
+/// <YYYY-MM-DD>` or `DOCUMENT_DATE`, and returns `args` without the flags.
+/// Without buckets nothing is counted over time.
+pub fn init(args: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut remaining = Vec::new();
+    let mut spec = std::env::var("BUCKETS").ok();
+    let mut date = std::env::var("DOCUMENT_DATE").ok();
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--buckets" {
+            spec = Some(iter.next().ok_or("--buckets expects a list of buckets")?);
+        } else if arg == "--date" {
+            date = Some(iter.next().ok_or("--date expects a date")?);
+        } else {
+            remaining.push(arg);
+        }
+    }
+
+    let mut granularities = Vec::new();
+    for name in spec
+        .as_deref()
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let granularity = Granularity::parse(name)?;
+        if !granularities.contains(&granularity) {
+            granularities.push(granularity);
+        }
+    }
+    let date = match date {
+        Some(date) => Some(
+            NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+                .map_err(|_| format!("Invalid document date \"{}\" (expected YYYY-MM-DD)", date))?,
+        ),
+        None => None,
+    };
+    _ = CONFIGURED.set(Config {
+        granularities,
+        date,
+    });
+    Ok(remaining)
+}
+
+fn configured() -> &'static Config {
+    CONFIGURED.get_or_init(|| Config {
+        granularities: Vec::new(),
+        date: None,
+    })
+}
+
+pub fn enabled() -> bool {
+    !configured().granularities.is_empty()
+}
+
+/// The partition of a 3-gram within a bucket of `three_grams_by_period`,
+/// picked from its first word so a busy bucket is spread over `SHARDS`
+/// partitions instead of one that grows without bound. FNV-1a keeps it
+/// stable across runs and platforms, unlike the std hasher.
+fn shard(word_1: &str) -> i32 {
+    let hash = word_1.bytes().fold(0x811c_9dc5_u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    });
+    (hash % SHARDS) as i32
+}
+
+/// The name of the year bucket of `year`.
+pub fn year_bucket(year: i32) -> String {
+    format!("{:04}", year)
+}
+
+/// Creates the bucket tables: `three_grams_by_bucket` holds the series of
+/// every 3-gram, `three_grams_by_period` every 3-gram of a bucket.
+pub async fn create(session: &Session) -> Result<(), Box<dyn Error>> {
+    session
+        .query(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {} (word_1 text, word_2 text, word_3 text, granularity text, bucket text, freq int, PRIMARY KEY ((word_1, word_2, word_3, granularity), bucket))",
+                corpus::table("three_grams_by_bucket")
+            ),
+            (),
+        )
+        .await?;
+    session
+        .query(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {} (bucket text, shard int, word_1 text, word_2 text, word_3 text, freq int, PRIMARY KEY ((bucket, shard), word_1, word_2, word_3))",
+                corpus::table("three_grams_by_period")
+            ),
+            (),
+        )
+        .await?;
+    Ok(())
+}
+
+/// The frequency of the 3-gram in `bucket`, 0 when it has none.
+pub async fn get_bucket(
+    session: &Session,
+    input: &specs::ThreeGramInput,
+    granularity: Granularity,
+    bucket: &str,
+) -> Result<i32, Box<dyn Error>> {
+    let prepared = statements::prepare(
+        session,
+        &format!(
+            "SELECT freq FROM {} WHERE word_1 = ? AND word_2 = ? AND word_3 = ? AND granularity = ? AND bucket = ?",
+            corpus::table("three_grams_by_bucket")
+        ),
+    )
+    .await?;
+    let row = session
+        .execute(
+            &prepared,
+            (
+                input.word_1.as_str(),
+                input.word_2.as_str(),
+                input.word_3.as_str(),
+                granularity.name(),
+                bucket,
+            ),
+        )
+        .await?
+        .maybe_first_row_typed::<(i32,)>()?;
+    Ok(row.map(|(freq,)| freq).unwrap_or(0))
+}
+
+/// Sets the frequency of the 3-gram in `bucket`, in both bucket tables at
+/// once.
+pub async fn set_bucket(
+    session: &Session,
+    input: &specs::ThreeGramInput,
+    granularity: Granularity,
+    bucket: &str,
+    freq: i32,
+) -> Result<(), Box<dyn Error>> {
+    let cqls = [
+        format!(
+            "INSERT INTO {} (word_1, word_2, word_3, granularity, bucket, freq) VALUES (?, ?, ?, ?, ?, ?)",
+            corpus::table("three_grams_by_bucket")
+        ),
+        format!(
+            "INSERT INTO {} (bucket, shard, word_1, word_2, word_3, freq) VALUES (?, ?, ?, ?, ?, ?)",
+            corpus::table("three_grams_by_period")
+        ),
+    ];
+    let batch = statements::prepare_batch(session, &[cqls[0].as_str(), cqls[1].as_str()]).await?;
+    let words = (
+        input.word_1.as_str(),
+        input.word_2.as_str(),
+        input.word_3.as_str(),
+    );
+    session
+        .batch(
+            &batch,
+            (
+                (words.0, words.1, words.2, granularity.name(), bucket, freq),
+                (bucket, shard(words.0), words.0, words.1, words.2, freq),
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Adds `count` to the configured buckets of the 3-gram. Does nothing when
+/// no buckets are configured.
+pub async fn record(
+    session: &Session,
+    input: &specs::ThreeGramInput,
+    count: i32,
+) -> Result<(), Box<dyn Error>> {
+    let config = configured();
+    let date = config.date.unwrap_or_else(|| Utc::now().date_naive());
+    for granularity in &config.granularities {
+        let bucket = granularity.bucket(date);
+        let freq = get_bucket(session, input, *granularity, &bucket).await?;
+        set_bucket(
+            session,
+            input,
+            *granularity,
+            &bucket,
+            freq.saturating_add(count),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// The frequency of the 3-gram in every stored bucket of `granularity`
+/// between `from` and `to`, both inclusive, in chronological order.
+pub async fn series(
+    session: &Session,
+    input: &specs::ThreeGramInput,
+    granularity: Granularity,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<Vec<(String, i32)>, Box<dyn Error>> {
+    let mut cql = format!(
+        "SELECT bucket, freq FROM {} WHERE word_1 = ? AND word_2 = ? AND word_3 = ? AND granularity = ?",
+        corpus::table("three_grams_by_bucket")
+    );
+    let mut values = vec![
+        input.word_1.clone(),
+        input.word_2.clone(),
+        input.word_3.clone(),
+        granularity.name().to_string(),
+    ];
+    for (bound, operator) in [(from, ">="), (to, "<=")] {
+        if let Some(bound) = bound {
+            if Granularity::of(bound)? != granularity {
+                return Err(format!("{} is not a {} bucket", bound, granularity.name()).into());
+            }
+            cql.push_str(&format!(" AND bucket {} ?", operator));
+            values.push(bound.to_string());
+        }
+    }
+
+    let prepared = statements::prepare(session, &cql).await?;
+    let rows = session.execute(&prepared, values).await?.rows;
+    let mut series = Vec::new();
+    if let Some(rows) = rows {
+        for row in rows.into_typed::<(String, i32)>() {
+            series.push(row?);
+        }
+    }
+    Ok(series)
+}
+
+/// Every 3-gram counted in `bucket` with its frequency, read shard by shard.
+async fn period(
+    session: &Session,
+    bucket: &str,
+) -> Result<HashMap<(String, String, String), i64>, Box<dyn Error>> {
+    let mut prepared = statements::prepare(
+        session,
+        &format!(
+            "SELECT word_1, word_2, word_3, freq FROM {} WHERE bucket = ? AND shard = ?",
+            corpus::table("three_grams_by_period")
+        ),
+    )
+    .await?;
+    prepared.set_page_size(PAGE_SIZE);
+
+    let mut three_grams = HashMap::new();
+    for shard in 0..SHARDS as i32 {
+        let mut rows = session
+            .execute_iter(prepared.clone(), (bucket, shard))
+            .await?
+            .into_typed::<(String, String, String, i32)>();
+        while let Some(row) = rows.next().await {
+            let (word_1, word_2, word_3, freq) = row?;
+            three_grams.insert((word_1, word_2, word_3), freq as i64);
+        }
+    }
+    Ok(three_grams)
+}
+
+/// Compares two buckets of the same granularity. Returns the 3-grams whose
+/// share grew from `period_a` to `period_b` and those whose share shrank,
+/// each ranked by log-likelihood and cut at `limit`.
+pub async fn trending(
+    session: &Session,
+    period_a: &str,
+    period_b: &str,
+    limit: usize,
+) -> Result<(Vec<Trend>, Vec<Trend>), Box<dyn Error>> {
+    if Granularity::of(period_a)? != Granularity::of(period_b)? {
+        return Err(format!(
+            "{} and {} are buckets of different sizes",
+            period_a, period_b
+        )
+        .into());
+    }
+    let three_grams_a = period(session, period_a).await?;
+    let three_grams_b = period(session, period_b).await?;
+    let total_a: i64 = three_grams_a.values().sum();
+    let total_b: i64 = three_grams_b.values().sum();
+
+    let trends: Vec<Trend> = three_grams_a
+        .keys()
+        .chain(three_grams_b.keys())
+        .unique()
+        .map(|key| {
+            let freq_a = three_grams_a.get(key).copied().unwrap_or(0);
+            let freq_b = three_grams_b.get(key).copied().unwrap_or(0);
+            Trend {
+                input: specs::ThreeGramInput::new(key.0.clone(), key.1.clone(), key.2.clone()),
+                freq_a,
+                freq_b,
+                log_likelihood: keyness(freq_a, total_a, freq_b, total_b).log_likelihood,
+            }
+        })
+        .sorted_by(|a, b| b.log_likelihood.total_cmp(&a.log_likelihood))
+        .collect();
+
+    let (mut rising, mut declining): (Vec<Trend>, Vec<Trend>) = trends
+        .into_iter()
+        .filter(|trend| per_million(trend.freq_a, total_a) != per_million(trend.freq_b, total_b))
+        .partition(|trend| per_million(trend.freq_b, total_b) > per_million(trend.freq_a, total_a));
+    rising.truncate(limit);
+    declining.truncate(limit);
+    Ok((rising, declining))
+}
+
+pub fn print_trends(title: &str, trends: &[Trend]) {
+    println!("{}:", title);
+    if trends.is_empty() {
+        println!(" (none)");
+    }
+    for trend in trends {
+        println!(
+            " {} {} {}: {} -> {}, log-likelihood {:.2}",
+            trend.input.word_1,
+            trend.input.word_2,
+            trend.input.word_3,
+            trend.freq_a,
+            trend.freq_b,
+            trend.log_likelihood
+        );
+    }
+}